@@ -0,0 +1,418 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::rayt::camera::{Camera, Projection};
+use crate::rayt::float3::{Float3, Point3, Vector3};
+use crate::rayt::obj::load_obj;
+use crate::rayt::scene::{load_scene, CameraDesc, RenderDesc, SceneDesc};
+use crate::rayt::shape::{self, ShapeList, SimpleScene};
+
+/// コマンドライン引数の定義
+/// 解像度やサンプル数などを再コンパイルせずに変更できるようにする
+#[derive(Debug, Parser)]
+#[command(version, about = "Rust ray tracer")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// ウィンドウを開かずにレンダリングし、画像ファイル(.png / .ppm)に書き出す
+    Render {
+        #[command(flatten)]
+        settings: RenderArgs,
+        /// 出力先のファイル。拡張子で形式を判定する
+        #[arg(short, long, default_value = "output.png", value_parser = parse_output)]
+        output: PathBuf,
+    },
+    /// ウィンドウを開いてレンダリング結果を表示する
+    View {
+        #[command(flatten)]
+        settings: RenderArgs,
+    },
+    /// ウィンドウを開かずに複数回レンダリングし、所要時間を表示する
+    Bench {
+        #[command(flatten)]
+        settings: RenderArgs,
+        /// 計測する回数
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+        runs: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SceneKind {
+    /// コーネルボックス
+    Cornell,
+    /// 地面の上に球を並べたシーン
+    Spheres,
+    /// 地面の上に大量の小さな球を格子状に並べたシーン
+    Grid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Accel {
+    /// 全ての形状を順番に調べる
+    List,
+    /// BVHを使う
+    Bvh,
+}
+
+impl SceneKind {
+    fn shapes(self) -> ShapeList {
+        match self {
+            SceneKind::Cornell => SimpleScene::cornell_box(),
+            SceneKind::Spheres => SimpleScene::spheres(),
+            SceneKind::Grid => SimpleScene::sphere_grid(64),
+        }
+    }
+
+    /// シーンごとのデフォルトのカメラ
+    fn default_camera(self) -> CameraDesc {
+        let (lookfrom, lookat, vfov) = match self {
+            SceneKind::Cornell => (
+                Float3::new(278.0, 278.0, -800.0),
+                Float3::new(278.0, 278.0, 0.0),
+                20.0,
+            ),
+            SceneKind::Spheres => (
+                Float3::new(0.0, 0.5, -2.0),
+                Float3::new(0.0, 0.0, 1.0),
+                50.0,
+            ),
+            SceneKind::Grid => (
+                Float3::new(0.0, 12.0, -40.0),
+                Float3::new(0.0, 0.0, 0.0),
+                45.0,
+            ),
+        };
+        CameraDesc::new(lookfrom, lookat, Float3::yaxis(), vfov)
+    }
+}
+
+/// 各サブコマンドで共通の設定
+/// 省略した値はシーンファイルの値、それも無ければデフォルト値を使う
+#[derive(Debug, Args)]
+pub struct RenderArgs {
+    /// 画像の幅(ピクセル) [default: 400]
+    #[arg(long, value_parser = parse_resolution)]
+    pub width: Option<u32>,
+    /// 画像の高さ(ピクセル) [default: 400]
+    #[arg(long, value_parser = parse_resolution)]
+    pub height: Option<u32>,
+    /// 1ピクセルあたりのサンプル数 [default: 100]
+    #[arg(short, long, value_parser = parse_samples)]
+    pub samples: Option<usize>,
+    /// レイの最大反射回数 [default: 50]
+    #[arg(short, long)]
+    pub depth: Option<usize>,
+    /// レンダリングする組み込みのシーン
+    #[arg(long, value_enum, default_value_t = SceneKind::Cornell, conflicts_with = "scene_file")]
+    pub scene: SceneKind,
+    /// シーンファイル(TOML)からシーンを読み込む
+    #[arg(long, value_name = "PATH")]
+    pub scene_file: Option<PathBuf>,
+    /// ワールドの交差判定に使うデータ構造
+    #[arg(long, value_enum, default_value_t = Accel::Bvh)]
+    pub accel: Accel,
+    /// シーンに追加するOBJファイル。複数回指定できる
+    #[arg(long, value_name = "PATH")]
+    pub obj: Vec<PathBuf>,
+    #[command(flatten)]
+    pub camera: CameraArgs,
+}
+
+/// カメラの設定。指定しなかった値はシーンの値を使う
+#[derive(Debug, Args)]
+pub struct CameraArgs {
+    /// カメラの位置 (例: 278,278,-800)
+    #[arg(long, value_name = "X,Y,Z", value_parser = parse_float3, allow_hyphen_values = true)]
+    pub lookfrom: Option<Point3>,
+    /// 注視点 (例: 278,278,0)
+    #[arg(long, value_name = "X,Y,Z", value_parser = parse_float3, allow_hyphen_values = true)]
+    pub lookat: Option<Point3>,
+    /// カメラの上方向 (例: 0,1,0)
+    #[arg(long, value_name = "X,Y,Z", value_parser = parse_float3, allow_hyphen_values = true)]
+    pub vup: Option<Vector3>,
+    /// 垂直方向の視野角(度)
+    #[arg(long, value_parser = parse_vfov)]
+    pub vfov: Option<f64>,
+    /// レンズの半径。0より大きくすると被写界深度が付く
+    #[arg(long, value_parser = parse_aperture)]
+    pub aperture: Option<f64>,
+    /// ピントが合う距離 [default: lookatまでの距離]
+    #[arg(long, value_parser = parse_focus_distance)]
+    pub focus_distance: Option<f64>,
+    /// 絞りの羽根の枚数。指定するとボケが正多角形になる
+    #[arg(long, value_parser = parse_blades)]
+    pub blades: Option<u32>,
+}
+
+/// 引数とシーンから決まった、レンダリングに必要なもの一式
+pub struct Setup {
+    /// ログなどに表示するシーンの名前
+    pub name: String,
+    pub scene: SimpleScene,
+    pub camera: Camera,
+    /// cameraを作成した設定。ビューアでカメラを動かすときに使う
+    pub camera_desc: CameraDesc,
+    pub width: u32,
+    pub height: u32,
+    pub samples: usize,
+    pub depth: usize,
+}
+
+impl RenderArgs {
+    /// シーンを読み込み、引数で上書きした設定でワールドとカメラを作成する
+    pub fn setup(&self) -> Result<Setup, String> {
+        let (name, desc) = match &self.scene_file {
+            Some(path) => (
+                path.display().to_string(),
+                load_scene(path).map_err(|e| e.to_string())?,
+            ),
+            None => (
+                format!("{:?}", self.scene),
+                SceneDesc {
+                    camera: self.scene.default_camera(),
+                    render: RenderDesc::default(),
+                    shapes: self.scene.shapes(),
+                    environment: None,
+                    lights: Vec::new(),
+                },
+            ),
+        };
+        let SceneDesc {
+            camera,
+            render,
+            mut shapes,
+            environment,
+            lights,
+        } = desc;
+        for path in &self.obj {
+            let obj = load_obj(path, None).map_err(|e| e.to_string())?;
+            shapes.push(Box::new(obj));
+        }
+        let accel = match self.accel {
+            Accel::List => shape::Accel::List,
+            Accel::Bvh => shape::Accel::Bvh,
+        };
+
+        let width = check(self.width.or(render.width).unwrap_or(400), "width", |v| {
+            check_resolution(*v)
+        })?;
+        let height = check(
+            self.height.or(render.height).unwrap_or(400),
+            "height",
+            |v| check_resolution(*v),
+        )?;
+        let samples = check(
+            self.samples.or(render.samples).unwrap_or(100),
+            "samples",
+            |v| check_samples(*v),
+        )?;
+        let depth = self.depth.or(render.depth).unwrap_or(50);
+        let camera = CameraDesc {
+            lookfrom: self.camera.lookfrom.unwrap_or(camera.lookfrom),
+            lookat: self.camera.lookat.unwrap_or(camera.lookat),
+            vup: self.camera.vup.unwrap_or(camera.vup),
+            vfov: check(self.camera.vfov.unwrap_or(camera.vfov), "vfov", |v| {
+                check_vfov(*v)
+            })?,
+            aperture: check(
+                self.camera.aperture.unwrap_or(camera.aperture),
+                "aperture",
+                |v| check_aperture(*v),
+            )?,
+            focus_distance: self
+                .camera
+                .focus_distance
+                .or(camera.focus_distance)
+                .map(|v| check(v, "focus_distance", |v| check_focus_distance(*v)))
+                .transpose()?,
+            blades: self
+                .camera
+                .blades
+                .or(camera.blades)
+                .map(|v| check(v, "blades", |v| check_blades(*v)))
+                .transpose()?,
+            blade_rotation: camera.blade_rotation,
+            shutter_open: camera.shutter_open,
+            shutter_close: camera.shutter_close,
+            projection: check(camera.projection, "projection", check_projection)?,
+            stereo: camera
+                .stereo
+                .map(|v| check(v, "stereo", |v| check_stereo(*v)))
+                .transpose()?,
+        };
+        if camera.shutter_close < camera.shutter_open {
+            return Err(
+                "invalid shutter: shutter_close must not be earlier than shutter_open".to_string(),
+            );
+        }
+
+        let mut scene = SimpleScene::from_shapes(shapes, accel);
+        if let Some(environment) = environment {
+            scene = scene.with_environment(environment);
+        }
+        for light in lights {
+            scene = scene.with_light(light);
+        }
+
+        Ok(Setup {
+            name,
+            scene,
+            camera: build_camera(&camera, width as f64 / height as f64)?,
+            camera_desc: camera,
+            width,
+            height,
+            samples,
+            depth,
+        })
+    }
+}
+
+/// 引数とシーンのデフォルト値からカメラを作成する
+fn build_camera(desc: &CameraDesc, aspect: f64) -> Result<Camera, String> {
+    let forward = desc.lookat - desc.lookfrom;
+    if forward.length_squared() == 0.0 {
+        return Err("lookfrom and lookat must be different points".to_string());
+    }
+    if desc.vup.cross(forward).length_squared() == 0.0 {
+        return Err("vup must not be parallel to the viewing direction".to_string());
+    }
+    Ok(desc.build(aspect))
+}
+
+/// シーンファイルから読み込んだ値も、コマンドラインと同じ条件で検証する
+fn check<T>(value: T, name: &str, f: impl Fn(&T) -> Result<(), String>) -> Result<T, String> {
+    f(&value).map_err(|e| format!("invalid {}: {}", name, e))?;
+    Ok(value)
+}
+
+const MAX_RESOLUTION: u32 = 16384;
+
+fn check_resolution(value: u32) -> Result<(), String> {
+    if value == 0 || value > MAX_RESOLUTION {
+        return Err(format!("must be between 1 and {}", MAX_RESOLUTION));
+    }
+    Ok(())
+}
+
+fn parse_resolution(s: &str) -> Result<u32, String> {
+    let value: u32 = s
+        .parse()
+        .map_err(|_| format!("`{}` is not a positive integer", s))?;
+    check_resolution(value)?;
+    Ok(value)
+}
+
+fn check_samples(value: usize) -> Result<(), String> {
+    if value == 0 {
+        return Err("must be at least 1".to_string());
+    }
+    Ok(())
+}
+
+fn parse_samples(s: &str) -> Result<usize, String> {
+    let value: usize = s
+        .parse()
+        .map_err(|_| format!("`{}` is not a positive integer", s))?;
+    check_samples(value)?;
+    Ok(value)
+}
+
+fn check_vfov(value: f64) -> Result<(), String> {
+    if !(value > 0.0 && value < 180.0) {
+        return Err("must be greater than 0 and less than 180 degrees".to_string());
+    }
+    Ok(())
+}
+
+fn parse_vfov(s: &str) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    check_vfov(value)?;
+    Ok(value)
+}
+
+fn check_aperture(value: f64) -> Result<(), String> {
+    if !(value >= 0.0 && value.is_finite()) {
+        return Err("must be a non-negative number".to_string());
+    }
+    Ok(())
+}
+
+fn parse_aperture(s: &str) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    check_aperture(value)?;
+    Ok(value)
+}
+
+fn check_focus_distance(value: f64) -> Result<(), String> {
+    if !(value > 0.0 && value.is_finite()) {
+        return Err("must be a positive number".to_string());
+    }
+    Ok(())
+}
+
+fn parse_focus_distance(s: &str) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    check_focus_distance(value)?;
+    Ok(value)
+}
+
+fn check_projection(value: &Projection) -> Result<(), String> {
+    match *value {
+        Projection::Orthographic { height } if !(height > 0.0 && height.is_finite()) => {
+            Err("orthographic height must be a positive number".to_string())
+        }
+        Projection::Fisheye { fov } if !(fov > 0.0 && fov <= 360.0) => {
+            Err("fisheye fov must be greater than 0 and at most 360 degrees".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_stereo(value: f64) -> Result<(), String> {
+    if !(value > 0.0 && value.is_finite()) {
+        return Err("eye separation must be a positive number".to_string());
+    }
+    Ok(())
+}
+
+fn check_blades(value: u32) -> Result<(), String> {
+    if value < 3 {
+        return Err("must be at least 3".to_string());
+    }
+    Ok(())
+}
+
+fn parse_blades(s: &str) -> Result<u32, String> {
+    let value: u32 = s
+        .parse()
+        .map_err(|_| format!("`{}` is not a positive integer", s))?;
+    check_blades(value)?;
+    Ok(value)
+}
+
+fn parse_float3(s: &str) -> Result<Float3, String> {
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("`{}` is not a comma separated list of numbers", s))?;
+    match values[..] {
+        [x, y, z] if values.iter().all(|v| v.is_finite()) => Ok(Float3::new(x, y, z)),
+        [_, _, _] => Err("values must be finite".to_string()),
+        _ => Err(format!("expected 3 values (X,Y,Z), got {}", values.len())),
+    }
+}
+
+fn parse_output(s: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(s);
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("png") || ext.eq_ignore_ascii_case("ppm") => Ok(path),
+        _ => Err("output file must end with .png or .ppm".to_string()),
+    }
+}
@@ -0,0 +1,164 @@
+use std::fmt;
+
+use crate::rayt::camera::{Camera, Projection};
+use crate::rayt::float3::{Float3, Vector3};
+use crate::rayt::quaternion::Quaternion;
+use crate::rayt::scene::CameraDesc;
+
+/// 1回のキー入力で移動する距離の、注視点までの距離に対する割合
+const MOVE_STEP: f64 = 0.05;
+/// マウスを1ピクセル動かしたときの回転角(度)
+const ORBIT_DEGREES_PER_PIXEL: f64 = 0.25;
+/// ホイール1段あたりに注視点までの距離に掛ける倍率
+const ZOOM_FACTOR: f64 = 0.9;
+/// 視線がvupと平行にならないように、仰角をこの角度(度)までに制限する
+const MAX_PITCH: f64 = 89.0;
+
+/// ビューアでのカメラ操作
+/// WASDで視線に沿って移動、マウスのドラッグで注視点の周りを回転、ホイールで注視点に近づく
+pub struct CameraController {
+    desc: CameraDesc,
+    aspect: f64,
+}
+
+impl CameraController {
+    pub fn new(desc: CameraDesc, aspect: f64) -> Self {
+        Self { desc, aspect }
+    }
+
+    pub fn camera(&self) -> Camera {
+        self.desc.build(self.aspect)
+    }
+
+    fn distance(&self) -> f64 {
+        (self.desc.lookat - self.desc.lookfrom).length()
+    }
+
+    fn forward(&self) -> Vector3 {
+        (self.desc.lookat - self.desc.lookfrom).normalize()
+    }
+
+    /// 画面の右方向。Camera::from_lookatのuと同じ向きになる
+    fn right(&self) -> Vector3 {
+        self.desc.vup.cross(-self.forward()).normalize()
+    }
+
+    /// カメラの向きを保ったまま、視線方向・右方向・上方向にstep単位で移動する
+    pub fn translate(&mut self, forward: f64, right: f64, up: f64) {
+        let step = self.distance() * MOVE_STEP;
+        let offset =
+            (self.forward() * forward + self.right() * right + self.desc.vup.normalize() * up)
+                * step;
+        self.desc.lookfrom += offset;
+        self.desc.lookat += offset;
+    }
+
+    /// 注視点を中心に、マウスの移動量(ピクセル)に応じて視点を回転する
+    pub fn orbit(&mut self, dx: f64, dy: f64) {
+        let up = self.desc.vup.normalize();
+        //左右の移動はvup周りの回転
+        let yaw = Quaternion::from_axis_angle(up, (-dx * ORBIT_DEGREES_PER_PIXEL).to_radians());
+        let offset = yaw.rotate(self.desc.lookfrom - self.desc.lookat);
+        //上下の移動は右方向の軸周りの回転。真上・真下を越えないように制限する
+        let pitch = 90.0
+            - offset
+                .normalize()
+                .dot(up)
+                .clamp(-1.0, 1.0)
+                .acos()
+                .to_degrees();
+        let angle = (pitch + dy * ORBIT_DEGREES_PER_PIXEL).clamp(-MAX_PITCH, MAX_PITCH) - pitch;
+        let tilt = Quaternion::from_axis_angle(offset.cross(up), angle.to_radians());
+        self.desc.lookfrom = self.desc.lookat + tilt.rotate(offset);
+    }
+
+    /// ホイールの段数に応じて注視点に近づく(負なら離れる)
+    pub fn zoom(&mut self, steps: f64) {
+        let offset = self.desc.lookfrom - self.desc.lookat;
+        self.desc.lookfrom = self.desc.lookat + offset * ZOOM_FACTOR.powf(steps);
+    }
+}
+
+/// シーンファイルの[camera]にそのまま貼り付けられる形式で出力する
+impl fmt::Display for CameraController {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let vector = |v: Float3| format!("[{}, {}, {}]", v.x(), v.y(), v.z());
+        writeln!(f, "[camera]")?;
+        writeln!(f, "lookfrom = {}", vector(self.desc.lookfrom))?;
+        writeln!(f, "lookat = {}", vector(self.desc.lookat))?;
+        writeln!(f, "vup = {}", vector(self.desc.vup))?;
+        write!(f, "vfov = {}", self.desc.vfov)?;
+        if self.desc.aperture > 0.0 {
+            write!(f, "\naperture = {}", self.desc.aperture)?;
+        }
+        if let Some(focus_distance) = self.desc.focus_distance {
+            write!(f, "\nfocus_distance = {}", focus_distance)?;
+        }
+        if let Some(blades) = self.desc.blades {
+            write!(f, "\nblades = {}", blades)?;
+            write!(f, "\nblade_rotation = {}", self.desc.blade_rotation)?;
+        }
+        match self.desc.projection {
+            Projection::Perspective => {}
+            Projection::Orthographic { height } => write!(
+                f,
+                "\nprojection = {{ type = \"orthographic\", height = {} }}",
+                height
+            )?,
+            Projection::Fisheye { fov } => {
+                write!(f, "\nprojection = {{ type = \"fisheye\", fov = {} }}", fov)?
+            }
+            Projection::Equirectangular => {
+                write!(f, "\nprojection = {{ type = \"equirectangular\" }}")?
+            }
+        }
+        if let Some(eye_separation) = self.desc.stereo {
+            write!(f, "\nstereo = {}", eye_separation)?;
+        }
+        if self.desc.shutter_close > self.desc.shutter_open {
+            write!(f, "\nshutter_open = {}", self.desc.shutter_open)?;
+            write!(f, "\nshutter_close = {}", self.desc.shutter_close)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller() -> CameraController {
+        let desc = CameraDesc::new(
+            Float3::new(0.0, 0.0, 10.0),
+            Float3::zero(),
+            Float3::yaxis(),
+            40.0,
+        );
+        CameraController::new(desc, 1.0)
+    }
+
+    #[test]
+    fn orbit_rotates_around_lookat() {
+        let mut controls = controller();
+        //左右の移動はvup周りに回る。-360ピクセルで90度
+        controls.orbit(-360.0, 0.0);
+        let expected = Float3::new(10.0, 0.0, 0.0);
+        assert!((controls.desc.lookfrom - expected).length() < 1e-9);
+        //上下に回っても注視点までの距離は変わらない
+        controls.orbit(0.0, 120.0);
+        assert!((controls.distance() - 10.0).abs() < 1e-9);
+        let elevation = controls.desc.lookfrom.normalize().dot(Float3::yaxis()).asin();
+        assert!((elevation.to_degrees() - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn orbit_stops_before_the_pole() {
+        let mut controls = controller();
+        controls.orbit(0.0, 10_000.0);
+        let elevation = controls.desc.lookfrom.normalize().dot(Float3::yaxis()).asin();
+        assert!((elevation.to_degrees() - MAX_PITCH).abs() < 1e-9);
+        controls.orbit(0.0, -20_000.0);
+        let elevation = controls.desc.lookfrom.normalize().dot(Float3::yaxis()).asin();
+        assert!((elevation.to_degrees() + MAX_PITCH).abs() < 1e-9);
+    }
+}
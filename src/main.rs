@@ -1,21 +1,27 @@
 // mod hoge;
 // use hoge::fuga::{func01, func02};
 
+mod cli;
+mod controls;
 mod rayt;
-use crate::{rayt::*, shape::Shape};
+use crate::rayt::*;
 
-use float3::Float3;
-use rayt::camera::Camera;
-use shape::{HitInfo, SimpleScene, Sphere};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-use env_logger;
-use log::{error, info, warn};
+use clap::Parser;
+use cli::{Accel, Cli, Command, RenderArgs, Setup};
+use controls::CameraController;
+use framebuffer::FrameBuffer;
 
-use rayon::prelude::*;
+use log::{error, info};
 
 use winit::{
     //Event と WindowEvent という二つの型（または列挙型）が winit::event モジュールからインポート
-    event::{Event, WindowEvent},
+    event::{
+        ElementState, Event, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode,
+        WindowEvent,
+    },
     //ControlFlow と EventLoop が winit::event_loop モジュールからインポート
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
@@ -29,48 +35,80 @@ use winit::{
 use pixels::{Pixels, SurfaceTexture};
 use winit::dpi::LogicalSize;
 
-pub const sample: usize = 1000;
-pub const depth: usize = 50;
-
 fn main() {
     env_logger::init();
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Render { settings, output } => render_to_file(&setup(&settings), output),
+        Command::View { settings } => run_viewer(setup(&settings)),
+        Command::Bench { settings, runs } => bench(&setup(&settings), settings.accel, runs),
+    }
+}
+
+fn setup(settings: &RenderArgs) -> Setup {
+    settings.setup().unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+/// ディスプレイの無い環境(CIなど)向けのオフラインレンダリング
+fn render_to_file(setup: &Setup, path: PathBuf) {
+    let start = Instant::now();
+    let mut framebuffer = FrameBuffer::new(setup.width, setup.height);
+    framebuffer.render(&setup.scene, &setup.camera, setup.samples, setup.depth);
+    info!(
+        "rendered {} {}x{} ({} spp) in {:?}",
+        setup.name,
+        setup.width,
+        setup.height,
+        setup.samples,
+        start.elapsed()
+    );
+
+    if let Err(e) = framebuffer.save(&path) {
+        error!("failed to write {}: {}", path.display(), e);
+        std::process::exit(1);
+    }
+    info!("wrote {}", path.display());
+}
+
+/// 同じ設定で複数回レンダリングし、1回あたりの時間とサンプル数/秒を表示する
+fn bench(setup: &Setup, accel: Accel, runs: u32) {
+    let mut framebuffer = FrameBuffer::new(setup.width, setup.height);
+    let mut total = Duration::ZERO;
+    for run in 1..=runs {
+        let start = Instant::now();
+        framebuffer.render(&setup.scene, &setup.camera, setup.samples, setup.depth);
+        let elapsed = start.elapsed();
+        total += elapsed;
+        println!("run {}/{}: {:?}", run, runs, elapsed);
+    }
+    let mean = total / runs;
+    let paths = setup.width as f64 * setup.height as f64 * setup.samples as f64;
+    println!(
+        "{} ({:?}), {}x{}, {} spp, depth {}: mean {:?}, {:.2} Msamples/s",
+        setup.name,
+        accel,
+        setup.width,
+        setup.height,
+        setup.samples,
+        setup.depth,
+        mean,
+        paths / mean.as_secs_f64() / 1e6
+    );
+}
+
+fn run_viewer(setup: Setup) {
     //event_loopの定義
     //これによって、ウィンドウ内での色々なイベントを取得できる
     let event_loop = EventLoop::new();
-    // let screenWidth = 1280;
-    // let screenHeight = 720;
-
-    // let screenWidth = 640;
-    // let screenHeight = 640;
-
-    let screenWidth = 400;
-    let screenHeight = 400;
-
-    // let screenWidth = 200;
-    // let screenHeight = 200;
-
-    // let camera = Camera::from_lookat(
-    //     Float3::new(0.5, 0.0, 0.0),
-    //     Float3::new(0.5, 0.0, 1.0),
-    //     Float3::new(0.0, 1.0, 0.0),
-    //     90.0,
-    //     16.0 / 9.0,
-    // );
-    // let ray = camera.ray(0.5, 0.5);
-    // println!("{:?}", ray.direction.normalize());
-
-    let camera = Camera::from_lookat(
-        Float3::new(278.0, 278.0, -800.0),
-        Float3::new(278.0, 278.0, 0.0),
-        Float3::new(0.0, 1.0, 0.0),
-        20.0,
-        1.0,
-    );
 
     //winitクレート(ライブラリ)を使用して、ウィンドウを作成する
     let window = WindowBuilder::new()
-        .with_title("Simple Window")
-        .with_inner_size(LogicalSize::new(screenWidth, screenHeight))
+        .with_title(&setup.name)
+        .with_inner_size(LogicalSize::new(setup.width, setup.height))
         .build(&event_loop)
         .unwrap();
 
@@ -84,13 +122,26 @@ fn main() {
     //pixelsクレートのPixelsを使用して、画像バッファの作成
     let mut pixels = Pixels::new(window_size.width, window_size.height, surface_texture).unwrap();
 
-    // let sphere = Sphere::new(Float3::new(0.0, -5.0, 0.5), 5.0);
-    let scene = SimpleScene::new();
+    let mut framebuffer = FrameBuffer::new(window_size.width, window_size.height);
+
+    let mut controller = CameraController::new(
+        setup.camera_desc.clone(),
+        window_size.width as f64 / window_size.height as f64,
+    );
+    let mut camera = controller.camera();
+    //左ボタンでドラッグ中のマウスの位置
+    let mut dragging = false;
+    let mut last_cursor: Option<(f64, f64)> = None;
 
     //move |event, _, control_flow
     //この引数はクロージャと呼ばれるもので、関数のように使用できる
     event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Wait;
+        //目標のサンプル数に達するまでは、イベントを待たずに描画を続ける
+        *control_flow = if framebuffer.sample_count() < setup.samples {
+            ControlFlow::Poll
+        } else {
+            ControlFlow::Wait
+        };
 
         //いわゆるswitch文
         match event {
@@ -100,40 +151,78 @@ fn main() {
             } => {
                 *control_flow = ControlFlow::Exit;
             }
+            Event::WindowEvent { event, .. } => {
+                //カメラを動かすイベント以外はここで戻る
+                match event {
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                        ..
+                    } => match key {
+                        VirtualKeyCode::W => controller.translate(1.0, 0.0, 0.0),
+                        VirtualKeyCode::S => controller.translate(-1.0, 0.0, 0.0),
+                        VirtualKeyCode::A => controller.translate(0.0, -1.0, 0.0),
+                        VirtualKeyCode::D => controller.translate(0.0, 1.0, 0.0),
+                        VirtualKeyCode::Q => controller.translate(0.0, 0.0, -1.0),
+                        VirtualKeyCode::E => controller.translate(0.0, 0.0, 1.0),
+                        //シーンファイルに貼り付けられるように現在のカメラを出力する
+                        VirtualKeyCode::P => {
+                            println!("{}", controller);
+                            return;
+                        }
+                        _ => return,
+                    },
+                    WindowEvent::MouseInput {
+                        state,
+                        button: MouseButton::Left,
+                        ..
+                    } => {
+                        dragging = state == ElementState::Pressed;
+                        return;
+                    }
+                    WindowEvent::CursorMoved { position, .. } => {
+                        let cursor = (position.x, position.y);
+                        let last = last_cursor.replace(cursor);
+                        match last {
+                            Some((x, y)) if dragging => {
+                                controller.orbit(cursor.0 - x, cursor.1 - y)
+                            }
+                            _ => return,
+                        }
+                    }
+                    WindowEvent::MouseWheel { delta, .. } => match delta {
+                        MouseScrollDelta::LineDelta(_, y) => controller.zoom(y as f64),
+                        //トラックパッドなどはピクセル単位なので、1段を適当な量に換算する
+                        MouseScrollDelta::PixelDelta(position) => {
+                            controller.zoom(position.y / 50.0)
+                        }
+                    },
+                    _ => return,
+                }
+                //カメラを動かしたら蓄積したサンプルを捨てて描画し直す
+                camera = controller.camera();
+                framebuffer.clear();
+                window.request_redraw();
+            }
+            //1パスごとに1サンプルずつ蓄積して再描画する
+            Event::MainEventsCleared if framebuffer.sample_count() < setup.samples => {
+                framebuffer.accumulate(&setup.scene, &camera, 1, setup.depth);
+                window.request_redraw();
+            }
             Event::RedrawRequested(_) => {
-                let frame = pixels.get_frame();
-
-                frame
-                    .chunks_exact_mut(4)
-                    .enumerate()
-                    .par_bridge()
-                    .for_each(|(i, pixel)| {
-                        let u = (i % window_size.width as usize) as f64 / window_size.width as f64;
-                        let v = 1.0
-                            - (i / window_size.width as usize) as f64 / window_size.height as f64;
-
-                        let ray = camera.ray(u, v);
-                        let mut color = (0..sample)
-                            .into_par_iter() // 並列イテレータ
-                            .fold(
-                                || Float3::new(0.0, 0.0, 0.0),
-                                |acc, _| acc + scene.trace(ray, depth),
-                            )
-                            .reduce(|| Float3::new(0.0, 0.0, 0.0), |acc, val| acc + val);
-                        color /= sample as f64;
-                        // let c = scene.trace(ray, 25).gamma(2.2);
-                        // let c = scene.trace(ray);
-
-                        // println!("{:?}", c);
-
-                        pixel[0] = (color.x() * 255.0) as u8;
-                        pixel[1] = (color.y() * 255.0) as u8;
-                        pixel[2] = (color.z() * 255.0) as u8;
-                        pixel[3] = 255;
-                    });
-                //}
-
+                framebuffer.copy_to_rgba8(pixels.get_frame());
                 pixels.render().unwrap();
+                //タイトルにこれまでのサンプル数を表示する
+                window.set_title(&format!(
+                    "{} - {}/{} spp",
+                    setup.name,
+                    framebuffer.sample_count(),
+                    setup.samples
+                ));
             }
             _ => (),
         }
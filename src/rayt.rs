@@ -1,7 +1,23 @@
+pub mod aabb;
+pub mod bvh;
 pub mod camera;
+pub mod environment;
 pub mod float3;
+pub mod framebuffer;
+pub mod image_texture;
+pub mod instance;
+pub mod light;
 pub mod math;
+pub mod matrix;
+pub mod mesh;
+pub mod microfacet;
+pub mod motion;
+pub mod noise;
+pub mod obj;
 pub mod quaternion;
 pub mod ray;
 pub mod render;
+pub mod scene;
 pub mod shape;
+pub mod sky;
+pub mod texture;
@@ -0,0 +1,108 @@
+use super::float3::{Float3, Point3};
+use super::ray::Ray;
+
+/// 軸に平行なバウンディングボックス(Axis-Aligned Bounding Box)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    pub const fn new(min: Point3, max: Point3) -> Self {
+        Self { min, max }
+    }
+
+    /// 何も含まないボックス。surroundingの初期値として使う
+    pub const fn empty() -> Self {
+        Self {
+            min: Float3::full(f64::INFINITY),
+            max: Float3::full(f64::NEG_INFINITY),
+        }
+    }
+
+    /// 2点から作成する。各成分の大小は問わない
+    pub fn from_points(a: Point3, b: Point3) -> Self {
+        Self {
+            min: Float3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z())),
+            max: Float3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z())),
+        }
+    }
+
+    /// 2つのボックスを囲むボックス
+    pub fn surrounding(&self, other: &Aabb) -> Self {
+        Self {
+            min: Float3::new(
+                self.min.x().min(other.min.x()),
+                self.min.y().min(other.min.y()),
+                self.min.z().min(other.min.z()),
+            ),
+            max: Float3::new(
+                self.max.x().max(other.max.x()),
+                self.max.y().max(other.max.y()),
+                self.max.z().max(other.max.z()),
+            ),
+        }
+    }
+
+    /// 点を含むように広げたボックス
+    pub fn grow(&self, p: Point3) -> Self {
+        self.surrounding(&Self::new(p, p))
+    }
+
+    /// 厚みが0の軸をdeltaだけ広げる。軸に平行な長方形など平面の形状で使う
+    pub fn pad(&self, delta: f64) -> Self {
+        let mut min = self.min;
+        let mut max = self.max;
+        for i in 0..3 {
+            if max.0[i] - min.0[i] < delta {
+                min.0[i] -= delta * 0.5;
+                max.0[i] += delta * 0.5;
+            }
+        }
+        Self { min, max }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|i| self.min.0[i] > self.max.0[i])
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extent(&self) -> Float3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let e = self.extent();
+        2.0 * (e.x() * e.y() + e.y() * e.z() + e.z() * e.x())
+    }
+
+    /// スラブ法による交差判定
+    /// inv_directionはray.directionの各成分の逆数
+    pub fn hit(&self, ray: &Ray, inv_direction: Float3, t_min: f64, t_max: f64) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+        for i in 0..3 {
+            let t0 = (self.min.0[i] - ray.origin.0[i]) * inv_direction.0[i];
+            let t1 = (self.max.0[i] - ray.origin.0[i]) * inv_direction.0[i];
+            let (t0, t1) = if inv_direction.0[i] < 0.0 {
+                (t1, t0)
+            } else {
+                (t0, t1)
+            };
+            // NaN(0 * inf)の場合はmax/minで無視される
+            t_min = t0.max(t_min);
+            t_max = t1.min(t_max);
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
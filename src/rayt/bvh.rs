@@ -0,0 +1,387 @@
+use super::aabb::Aabb;
+use super::float3::Float3;
+use super::ray::Ray;
+use super::shape::{HitInfo, Shape};
+
+/// SAHの評価に使うビンの数
+const BIN_COUNT: usize = 12;
+/// これ以下の数の形状しか含まないノードは分割しない
+const MAX_LEAF_SIZE: usize = 2;
+/// ノードの走査コストと形状の交差判定コストの比
+const TRAVERSAL_COST: f64 = 0.125;
+
+#[derive(Debug)]
+enum BvhNode {
+    Leaf {
+        bbox: Aabb,
+        first: usize,
+        count: usize,
+    },
+    /// 左の子は常に自身の直後に格納されるので、右の子のインデックスだけを持つ
+    Interior {
+        bbox: Aabb,
+        right: usize,
+        axis: usize,
+    },
+}
+
+impl BvhNode {
+    fn bbox(&self) -> &Aabb {
+        match self {
+            BvhNode::Leaf { bbox, .. } | BvhNode::Interior { bbox, .. } => bbox,
+        }
+    }
+}
+
+/// Bounding Volume Hierarchy
+/// ShapeListの代わりにワールドとして使うことで、交差判定を形状の数に対して対数時間で行う
+/// ノードは配列に深さ優先の順番で並べ、SAH(Surface Area Heuristic)で分割位置を決める
+#[derive(Debug)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    objects: Vec<Box<dyn Shape>>,
+}
+
+struct BuildItem {
+    index: usize,
+    bbox: Aabb,
+    centroid: Float3,
+}
+
+#[derive(Copy, Clone)]
+struct Bin {
+    bbox: Aabb,
+    count: usize,
+}
+
+impl Bvh {
+    pub fn new(objects: Vec<Box<dyn Shape>>) -> Self {
+        let mut items: Vec<BuildItem> = objects
+            .iter()
+            .enumerate()
+            .map(|(index, object)| {
+                let bbox = object.bounding_box();
+                BuildItem {
+                    index,
+                    bbox,
+                    centroid: bbox.centroid(),
+                }
+            })
+            .collect();
+
+        let mut nodes = Vec::with_capacity(objects.len() * 2);
+        if !items.is_empty() {
+            Self::build(&mut nodes, &mut items, 0);
+        }
+
+        // 葉が連続した範囲を参照できるように、形状を構築後の順番に並べ替える
+        let mut slots: Vec<Option<Box<dyn Shape>>> = objects.into_iter().map(Some).collect();
+        let objects = items
+            .iter()
+            .map(|item| slots[item.index].take().unwrap())
+            .collect();
+
+        Self { nodes, objects }
+    }
+
+    /// items[..]を含むノードを作成し、そのインデックスを返す
+    /// offsetはitemsの先頭がobjects全体の何番目にあたるか
+    fn build(nodes: &mut Vec<BvhNode>, items: &mut [BuildItem], offset: usize) -> usize {
+        let bbox = items
+            .iter()
+            .fold(Aabb::empty(), |acc, item| acc.surrounding(&item.bbox));
+        let node_index = nodes.len();
+        let leaf = BvhNode::Leaf {
+            bbox,
+            first: offset,
+            count: items.len(),
+        };
+        if items.len() <= MAX_LEAF_SIZE {
+            nodes.push(leaf);
+            return node_index;
+        }
+
+        let centroid_bounds = items
+            .iter()
+            .fold(Aabb::empty(), |acc, item| acc.grow(item.centroid));
+        let Some((axis, split)) = Self::find_split(items, &bbox, &centroid_bounds) else {
+            nodes.push(leaf);
+            return node_index;
+        };
+
+        let mid = partition(items, |item| {
+            Self::bin_index(item.centroid.0[axis], &centroid_bounds, axis) <= split
+        });
+        // 浮動小数点の誤差などで片側が空になった場合は中央で分ける
+        let mid = if mid == 0 || mid == items.len() {
+            items.sort_by(|a, b| a.centroid.0[axis].total_cmp(&b.centroid.0[axis]));
+            items.len() / 2
+        } else {
+            mid
+        };
+
+        nodes.push(BvhNode::Interior {
+            bbox,
+            right: 0,
+            axis,
+        });
+        let (left_items, right_items) = items.split_at_mut(mid);
+        Self::build(nodes, left_items, offset);
+        let right_index = Self::build(nodes, right_items, offset + mid);
+        if let BvhNode::Interior { right, .. } = &mut nodes[node_index] {
+            *right = right_index;
+        }
+        node_index
+    }
+
+    fn bin_index(value: f64, centroid_bounds: &Aabb, axis: usize) -> usize {
+        let min = centroid_bounds.min.0[axis];
+        let extent = centroid_bounds.max.0[axis] - min;
+        let i = ((value - min) / extent * BIN_COUNT as f64) as usize;
+        i.min(BIN_COUNT - 1)
+    }
+
+    /// SAHのコストが最小になる(軸, ビンの境界)を返す。分割しない方が安い場合はNone
+    fn find_split(
+        items: &[BuildItem],
+        bbox: &Aabb,
+        centroid_bounds: &Aabb,
+    ) -> Option<(usize, usize)> {
+        let leaf_cost = items.len() as f64;
+        let parent_area = bbox.surface_area();
+        let mut best: Option<(usize, usize)> = None;
+        let mut best_cost = leaf_cost;
+
+        for axis in 0..3 {
+            if centroid_bounds.max.0[axis] - centroid_bounds.min.0[axis] <= 0.0 {
+                continue;
+            }
+            let mut bins = [Bin {
+                bbox: Aabb::empty(),
+                count: 0,
+            }; BIN_COUNT];
+            for item in items {
+                let bin = &mut bins[Self::bin_index(item.centroid.0[axis], centroid_bounds, axis)];
+                bin.bbox = bin.bbox.surrounding(&item.bbox);
+                bin.count += 1;
+            }
+
+            // 右側から累積した面積と個数
+            let mut right_area = [0.0; BIN_COUNT];
+            let mut right_count = [0; BIN_COUNT];
+            let mut acc = Bin {
+                bbox: Aabb::empty(),
+                count: 0,
+            };
+            for i in (1..BIN_COUNT).rev() {
+                acc.bbox = acc.bbox.surrounding(&bins[i].bbox);
+                acc.count += bins[i].count;
+                right_area[i] = acc.bbox.surface_area();
+                right_count[i] = acc.count;
+            }
+
+            let mut left = Bin {
+                bbox: Aabb::empty(),
+                count: 0,
+            };
+            for split in 0..BIN_COUNT - 1 {
+                left.bbox = left.bbox.surrounding(&bins[split].bbox);
+                left.count += bins[split].count;
+                if left.count == 0 || right_count[split + 1] == 0 {
+                    continue;
+                }
+                let cost = TRAVERSAL_COST
+                    + (left.bbox.surface_area() * left.count as f64
+                        + right_area[split + 1] * right_count[split + 1] as f64)
+                        / parent_area;
+                if cost < best_cost {
+                    best_cost = cost;
+                    best = Some((axis, split));
+                }
+            }
+        }
+        best
+    }
+}
+
+/// predがtrueになる要素を前に集め、その個数を返す
+fn partition<T>(items: &mut [T], pred: impl Fn(&T) -> bool) -> usize {
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+impl Shape for Bvh {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inv_direction = Float3::from_iter(ray.direction.0.iter().map(|d| d.recip()));
+        let mut hit_info: Option<HitInfo> = None;
+        let mut closest_so_far = t1;
+        let mut stack: Vec<usize> = Vec::with_capacity(64);
+        stack.push(0);
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bbox().hit(ray, inv_direction, t0, closest_so_far) {
+                continue;
+            }
+            match *node {
+                BvhNode::Leaf { first, count, .. } => {
+                    for object in &self.objects[first..first + count] {
+                        if let Some(info) = object.hit(ray, t0, closest_so_far) {
+                            closest_so_far = info.t;
+                            hit_info = Some(info);
+                        }
+                    }
+                }
+                BvhNode::Interior { right, axis, .. } => {
+                    let left = index + 1;
+                    // レイの向きに近い側の子を先に調べることで、遠い側の子を早く枝刈りできる
+                    if inv_direction.0[axis] < 0.0 {
+                        stack.push(left);
+                        stack.push(right);
+                    } else {
+                        stack.push(right);
+                        stack.push(left);
+                    }
+                }
+            }
+        }
+        hit_info
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.nodes
+            .first()
+            .map_or(Aabb::empty(), |node| *node.bbox())
+    }
+
+    fn collect_lights(&self, lights: &mut Vec<Box<dyn Shape>>) {
+        for object in &self.objects {
+            object.collect_lights(lights);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::rayt::mesh::Triangle;
+    use crate::rayt::render::Lambertian;
+    use crate::rayt::shape::{ShapeList, SimpleScene, Sphere};
+
+    /// centerを中心に一辺2 * half_extentの立方体の中からランダムなレイを飛ばし、
+    /// 線形のリストとBVHが同じ交差を返すことを確かめる
+    fn assert_same_hits(make: impl Fn() -> ShapeList, center: Float3, half_extent: f64) {
+        let list = make();
+        let bvh = make().into_bvh();
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut hits = 0;
+        for _ in 0..20_000 {
+            let origin = center
+                + Float3::new(
+                    rng.gen_range(-half_extent..half_extent),
+                    rng.gen_range(-half_extent..half_extent),
+                    rng.gen_range(-half_extent..half_extent),
+                );
+            let direction = Float3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            )
+            .normalize();
+            let ray = Ray::new(origin, direction);
+            match (
+                list.hit(&ray, 0.001, f64::MAX),
+                bvh.hit(&ray, 0.001, f64::MAX),
+            ) {
+                (None, None) => {}
+                (Some(a), Some(b)) => {
+                    hits += 1;
+                    assert!((a.t - b.t).abs() <= 1e-9 * a.t.max(1.0), "{ray:?}");
+                    assert!((a.p - b.p).length() <= 1e-9 * a.t.max(1.0), "{ray:?}");
+                    assert!((a.n - b.n).length() <= 1e-9, "{ray:?}");
+                }
+                (a, b) => panic!(
+                    "list hit: {}, bvh hit: {} for {ray:?}",
+                    a.is_some(),
+                    b.is_some()
+                ),
+            }
+        }
+        //ほとんど当たらないテストでは意味が無いので、当たったレイの数も確かめる
+        assert!(hits > 1000, "only {hits} rays hit");
+    }
+
+    #[test]
+    fn random_spheres_match_list() {
+        let make = || {
+            let mut rng = StdRng::seed_from_u64(3);
+            let material = Arc::new(Lambertian::new(Float3::full(0.5)));
+            let mut list = ShapeList::new();
+            for _ in 0..400 {
+                let center = Float3::new(
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..10.0),
+                );
+                let radius = rng.gen_range(0.1..1.0);
+                list.push(Box::new(Sphere::new(center, radius, material.clone())));
+            }
+            list
+        };
+        assert_same_hits(make, Float3::zero(), 12.0);
+    }
+
+    #[test]
+    fn cornell_box_matches_list() {
+        assert_same_hits(SimpleScene::cornell_box, Float3::full(277.5), 270.0);
+    }
+
+    #[test]
+    fn triangle_soup_matches_list() {
+        let make = || {
+            let mut rng = StdRng::seed_from_u64(2);
+            let material = Arc::new(Lambertian::new(Float3::full(0.5)));
+            let mut list = ShapeList::new();
+            for _ in 0..500 {
+                let p0 = Float3::new(
+                    rng.gen_range(-5.0..5.0),
+                    rng.gen_range(-5.0..5.0),
+                    rng.gen_range(-5.0..5.0),
+                );
+                let mut vertex = || {
+                    p0 + Float3::new(
+                        rng.gen_range(-1.0..1.0),
+                        rng.gen_range(-1.0..1.0),
+                        rng.gen_range(-1.0..1.0),
+                    )
+                };
+                let (p1, p2) = (vertex(), vertex());
+                list.push(Box::new(Triangle::new(p0, p1, p2, material.clone())));
+            }
+            list
+        };
+        assert_same_hits(make, Float3::zero(), 6.0);
+    }
+
+    #[test]
+    fn empty_bvh_never_hits() {
+        let bvh = Bvh::new(Vec::new());
+        let ray = Ray::new(Float3::zero(), Float3::xaxis());
+        assert!(bvh.hit(&ray, 0.001, f64::MAX).is_none());
+        assert!(bvh.bounding_box().is_empty());
+    }
+}
@@ -1,18 +1,85 @@
+use rand::Rng;
+use serde::Deserialize;
+
 use super::float3::{Point3, Vector3};
+use super::math::{PI, PI2};
 use super::ray::Ray;
 
+/// レンズの絞りの形。ボケの形になる
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aperture {
+    Circle,
+    /// 羽根の枚数がbladesの正多角形。rotationは多角形の回転角(度)
+    Polygon {
+        blades: u32,
+        rotation: f64,
+    },
+}
+
+impl Aperture {
+    /// 半径1の絞りの中の点を一様に選ぶ
+    fn sample(&self) -> (f64, f64) {
+        let mut rng = rand::thread_rng();
+        match *self {
+            Aperture::Circle => {
+                let r = rng.gen::<f64>().sqrt();
+                let (sin, cos) = (PI2 * rng.gen::<f64>()).sin_cos();
+                (r * cos, r * sin)
+            }
+            Aperture::Polygon { blades, rotation } => {
+                //中心と隣り合う2頂点からなる三角形を1つ選び、その中で一様に選ぶ
+                let step = PI2 / blades as f64;
+                let a0 = rotation.to_radians() + step * rng.gen_range(0..blades) as f64;
+                let a1 = a0 + step;
+                let s = rng.gen::<f64>().sqrt();
+                let b = rng.gen::<f64>();
+                (
+                    s * ((1.0 - b) * a0.cos() + b * a1.cos()),
+                    s * ((1.0 - b) * a0.sin() + b * a1.sin()),
+                )
+            }
+        }
+    }
+}
+
+/// 投影方法
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Projection {
+    /// 透視投影。視野角はvfovで決まる
+    #[default]
+    Perspective,
+    /// 平行投影。heightは画面の縦方向に写る範囲の大きさ
+    Orthographic { height: f64 },
+    /// 等距離射影の魚眼。fovは画面の縦方向に内接する円の視野角(度)
+    Fisheye { fov: f64 },
+    /// 全天球のパノラマ(正距円筒図法)。横方向が経度、縦方向が緯度になる
+    Equirectangular,
+}
+
 pub struct Camera {
     pub origin: Point3,
     pub u: Vector3,
     pub v: Vector3,
     pub w: Vector3,
+    /// 画面の右方向、上方向、視線方向の単位ベクトル
+    right: Vector3,
+    up: Vector3,
+    forward: Vector3,
+    /// 画面の縦横比(幅 / 高さ)
+    aspect: f64,
+    projection: Projection,
+    /// 立体視の左右の目の間隔。指定すると画面の上半分に左目、下半分に右目の画像を並べる
+    stereo: Option<f64>,
+    /// レンズの半径。0ならピンホールカメラになる
+    lens_radius: f64,
+    aperture: Aperture,
+    /// シャッターが開いている時刻の範囲。レイの時刻はこの中から一様に選ぶ
+    shutter_open: f64,
+    shutter_close: f64,
 }
 
 impl Camera {
-    pub fn new(origin: Point3, u: Vector3, v: Vector3, w: Vector3) -> Self {
-        Self { origin, u, v, w }
-    }
-
     pub fn from_lookat(
         origin: Vector3,
         lookat: Vector3,
@@ -32,13 +99,212 @@ impl Camera {
             u: xw * 2.0,
             v: yh * 2.0,
             w: origin - xw - yh - w,
+            right: x,
+            up: y,
+            forward: -w,
+            aspect,
+            projection: Projection::Perspective,
+            stereo: None,
+            lens_radius: 0.0,
+            aperture: Aperture::Circle,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+
+    /// 薄レンズモデルで被写界深度を付ける。透視投影の場合だけ使われる
+    /// lens_radiusはレンズの半径、focus_distanceはピントが合う平面までの距離
+    pub fn with_lens(mut self, lens_radius: f64, focus_distance: f64) -> Self {
+        self.lens_radius = lens_radius;
+        //スクリーンをピントが合う平面まで移動する
+        self.with_screen_distance(focus_distance)
+    }
+
+    /// 透視投影のスクリーンを視点から距離distanceの平面に置き直す。写る範囲は変わらない
+    fn with_screen_distance(mut self, distance: f64) -> Self {
+        let current = (self.w + self.u * 0.5 + self.v * 0.5 - self.origin).dot(self.forward);
+        let scale = distance / current;
+        self.u = self.u * scale;
+        self.v = self.v * scale;
+        self.w = self.origin + (self.w - self.origin) * scale;
+        self
+    }
+
+    pub fn with_aperture(mut self, aperture: Aperture) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    /// 時刻openからcloseまでシャッターを開けて、動く形状にモーションブラーを付ける
+    pub fn with_shutter(mut self, open: f64, close: f64) -> Self {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
+    pub fn with_projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self
+    }
+
+    /// 左右の目の間隔がeye_separationの立体視の画像にする
+    /// 透視投影では、左右の画像の視差はconvergence_distanceの距離にある平面で0になる
+    /// from_lookatのaspectには片目分の画面(画像の半分の高さ)の縦横比を渡す
+    pub fn with_stereo(mut self, eye_separation: f64, convergence_distance: f64) -> Self {
+        self.stereo = Some(eye_separation);
+        self.with_screen_distance(convergence_distance)
+    }
+
+    /// 画像の縦のピクセル数がrowsのときの、1ピクセル分のレイの(原点での太さ, 距離1あたりの広がり)
+    pub fn pixel_cone(&self, rows: u32) -> (f64, f64) {
+        //立体視では片目の画像の高さになる
+        let rows = if self.stereo.is_some() {
+            rows / 2
+        } else {
+            rows
+        }
+        .max(1) as f64;
+        match self.projection {
+            Projection::Perspective => {
+                let distance = (self.w + self.u * 0.5 + self.v * 0.5 - self.origin).length();
+                (0.0, self.v.length() / distance / rows)
+            }
+            Projection::Orthographic { height } => (height / rows, 0.0),
+            Projection::Fisheye { fov } => (0.0, fov.to_radians() / rows),
+            Projection::Equirectangular => (0.0, PI / rows),
+        }
+    }
+
+    /// 画面上の点(u, v)を通るレイ。uは左から右、vは下から上に0から1
+    /// 魚眼の円の外など、何も写らない点ではNone
+    pub fn ray(&self, u: f64, v: f64) -> Option<Ray> {
+        //立体視では上半分を左目、下半分を右目として、それぞれの中での座標に直す
+        let (v, eye) = match self.stereo {
+            Some(separation) if v >= 0.5 => ((v - 0.5) * 2.0, -separation * 0.5),
+            Some(separation) => (v * 2.0, separation * 0.5),
+            None => (v, 0.0),
+        };
+        let time = if self.shutter_close > self.shutter_open {
+            self.shutter_open
+                + rand::thread_rng().gen::<f64>() * (self.shutter_close - self.shutter_open)
+        } else {
+            self.shutter_open
+        };
+
+        let (origin, direction) = match self.projection {
+            Projection::Perspective => {
+                let mut origin = self.origin + self.right * eye;
+                if self.lens_radius > 0.0 {
+                    let (x, y) = self.aperture.sample();
+                    origin += (self.right * x + self.up * y) * self.lens_radius;
+                }
+                //目をずらしてもスクリーンは共有するので、スクリーンの距離で視差が0になる
+                (origin, self.w + self.u * u + self.v * v - origin)
+            }
+            Projection::Orthographic { height } => {
+                let x = (u - 0.5) * height * self.aspect;
+                let y = (v - 0.5) * height;
+                (
+                    self.origin + self.right * (x + eye) + self.up * y,
+                    self.forward,
+                )
+            }
+            Projection::Fisheye { fov } => {
+                //画面の中心からの距離が視線方向からの角度に比例する
+                let x = (u - 0.5) * 2.0 * self.aspect;
+                let y = (v - 0.5) * 2.0;
+                let r = (x * x + y * y).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                let theta = r * fov.to_radians() * 0.5;
+                let side = if r > 0.0 {
+                    (self.right * x + self.up * y) / r
+                } else {
+                    self.right
+                };
+                (
+                    self.origin + self.right * eye,
+                    self.forward * theta.cos() + side * theta.sin(),
+                )
+            }
+            Projection::Equirectangular => {
+                let phi = (u - 0.5) * PI2;
+                let theta = (v - 0.5) * PI;
+                let horizontal = self.forward * phi.cos() + self.right * phi.sin();
+                //全方位で立体視になるように、目は水平方向の視線と垂直な方向にずらす(ODS)
+                let side = self.forward * -phi.sin() + self.right * phi.cos();
+                (
+                    self.origin + side * eye,
+                    horizontal * theta.cos() + self.up * theta.sin(),
+                )
+            }
+        };
+        Some(Ray::with_time(origin, direction, time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rayt::float3::Float3;
+
+    /// レイが視点から視線方向に距離distanceの平面と交わる点
+    fn point_at_distance(camera: &Camera, ray: &Ray, distance: f64) -> Point3 {
+        let plane = camera.origin + camera.forward * distance;
+        let t = (plane - ray.origin).dot(camera.forward) / ray.direction.dot(camera.forward);
+        ray.origin + ray.direction * t
+    }
+
+    fn stereo_camera() -> Camera {
+        //コーネルボックス程度の大きさのシーンを、目の間隔20で見る
+        Camera::from_lookat(
+            Float3::new(278.0, 278.0, -800.0),
+            Float3::new(278.0, 278.0, 280.0),
+            Float3::yaxis(),
+            20.0,
+            1.0,
+        )
+    }
+
+    #[test]
+    fn stereo_perspective_converges_at_distance() {
+        let camera = stereo_camera().with_stereo(20.0, 1080.0);
+        for (u, v) in [(0.5, 0.5), (0.1, 0.2), (0.9, 0.8), (0.3, 0.95)] {
+            //上半分が左目、下半分が右目で、同じ画面上の点を見ている
+            let left = camera.ray(u, 0.5 + v * 0.5).unwrap();
+            let right = camera.ray(u, v * 0.5).unwrap();
+            assert!(((left.origin - right.origin) + camera.right * 20.0).length() < 1e-9);
+            let p = point_at_distance(&camera, &left, 1080.0);
+            let q = point_at_distance(&camera, &right, 1080.0);
+            assert!((p - q).length() < 1e-6, "{p:?} != {q:?}");
+            //視差を付けても、目から見た向きは視線方向からほとんど変わらない
+            for ray in [left, right] {
+                assert!(ray.direction.normalize().dot(camera.forward) > 0.9);
+            }
         }
+        //画面の中心は注視点を向く
+        let center = camera.ray(0.5, 0.75).unwrap();
+        let p = point_at_distance(&camera, &center, 1080.0);
+        assert!((p - Float3::new(278.0, 278.0, 280.0)).length() < 1e-6);
     }
 
-    pub fn ray(&self, u: f64, v: f64) -> Ray {
-        Ray {
-            origin: self.origin,
-            direction: self.w + self.u * u + self.v * v - self.origin,
+    #[test]
+    fn stereo_and_lens_share_the_focus_plane() {
+        let pinhole = stereo_camera().with_stereo(20.0, 1080.0);
+        let lens = stereo_camera()
+            .with_stereo(20.0, 1080.0)
+            .with_lens(10.0, 1080.0);
+        //写る範囲は変わらない
+        let (a, b) = (pinhole.pixel_cone(400).1, lens.pixel_cone(400).1);
+        assert!((a - b).abs() < 1e-12, "{a} != {b}");
+        for (u, v) in [(0.5, 0.25), (0.2, 0.7), (0.8, 0.1)] {
+            let expected = point_at_distance(&pinhole, &pinhole.ray(u, v).unwrap(), 1080.0);
+            for _ in 0..100 {
+                let ray = lens.ray(u, v).unwrap();
+                let p = point_at_distance(&lens, &ray, 1080.0);
+                assert!((p - expected).length() < 1e-6, "{p:?} != {expected:?}");
+            }
         }
     }
 }
@@ -0,0 +1,280 @@
+use std::fmt::Debug;
+use std::path::Path;
+
+use image::ImageError;
+use rand::Rng;
+
+use super::float3::{Color, Float3, Vector3};
+use super::image_texture::{self, ColorSpace};
+use super::math::{PI, PI2};
+
+/// レイがどの物体にも当たらなかったときに届く、無限に遠くからの光
+/// directionはいずれも正規化済みで、シーンの原点から外に向かう方向
+pub trait Environment: Sync + Send + Debug {
+    fn radiance(&self, direction: Vector3) -> Color;
+
+    /// 光源として直接サンプリングする方向と、その確率密度(立体角あたり)
+    /// デフォルトでは全方向から一様に選ぶ
+    fn sample(&self) -> (Vector3, f64) {
+        (Float3::randpm_unit_vector(), 1.0 / (2.0 * PI2))
+    }
+
+    /// sampleがdirectionを選ぶ確率密度
+    fn pdf(&self, _direction: Vector3) -> f64 {
+        1.0 / (2.0 * PI2)
+    }
+}
+
+/// どの方向からも同じ色の光が届く
+#[derive(Debug, Clone)]
+pub struct ConstantEnvironment {
+    color: Color,
+}
+
+impl ConstantEnvironment {
+    pub fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Environment for ConstantEnvironment {
+    fn radiance(&self, _direction: Vector3) -> Color {
+        self.color
+    }
+}
+
+/// 真下がbottom、真上がtopの色になる、上下方向のグラデーションの空
+#[derive(Debug, Clone)]
+pub struct SkyGradient {
+    bottom: Color,
+    top: Color,
+}
+
+impl SkyGradient {
+    pub fn new(bottom: Color, top: Color) -> Self {
+        Self { bottom, top }
+    }
+}
+
+impl Environment for SkyGradient {
+    fn radiance(&self, direction: Vector3) -> Color {
+        let t = 0.5 * (direction.y() + 1.0);
+        self.bottom.lerp(self.top, t)
+    }
+}
+
+/// 正距円筒図法の画像で表す全天球の光(HDRI)
+/// 画像の中心が+z、右に行くほど-x(+zを向いたときの右)の方向で、上端が真上になる
+/// 明るい画素ほど多く選ぶように重点的サンプリングするので、太陽の写った画像でも早く収束する
+#[derive(Debug)]
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    /// 上の行から順に並べたリニアな色
+    pixels: Vec<Color>,
+    /// y軸周りの回転(ラジアン)
+    rotation: f64,
+    intensity: f64,
+    /// 画像上の点(s, t)を選ぶ分布。sは左から右、tは上から下に0から1
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// 幅width、高さheightで、上の行から順に並べたリニアな色から作る
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert!(width > 0 && height > 0, "image must not be empty");
+        assert_eq!(pixels.len(), width * height, "image size mismatch");
+        //各画素の明るさに、その画素が占める立体角の大きさ(緯度のcos)を掛けた値に比例させる
+        let rows = (0..height)
+            .map(|y| {
+                let cos = Self::elevation((y as f64 + 0.5) / height as f64).cos();
+                (0..width)
+                    .map(|x| luminance(pixels[y * width + x]) * cos)
+                    .collect()
+            })
+            .collect();
+        Self {
+            width,
+            height,
+            pixels,
+            rotation: 0.0,
+            intensity: 1.0,
+            distribution: Distribution2D::new(rows),
+        }
+    }
+
+    /// HDR(Radiance)、OpenEXRの画像を読み込む。PNGやJPEGの画像はsRGBからリニアに変換する
+    pub fn load(path: &Path) -> Result<Self, ImageError> {
+        let (width, height, pixels) = image_texture::load_pixels(path, ColorSpace::Srgb)?;
+        Ok(Self::new(width, height, pixels))
+    }
+
+    /// y軸周りにdegrees度回転する
+    pub fn with_rotation(mut self, degrees: f64) -> Self {
+        self.rotation = degrees.to_radians();
+        self
+    }
+
+    /// 明るさを何倍にするか
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// 画像のt(上から下に0から1)での緯度(上が正)
+    fn elevation(t: f64) -> f64 {
+        PI * (0.5 - t)
+    }
+
+    /// 方向から画像上の点(s, t)を求める
+    fn direction_to_image(&self, direction: Vector3) -> (f64, f64) {
+        let phi = (-direction.x()).atan2(direction.z()) - self.rotation;
+        let s = (phi / PI2 + 0.5).rem_euclid(1.0);
+        let t = 0.5 - direction.y().clamp(-1.0, 1.0).asin() / PI;
+        (s, t)
+    }
+
+    fn image_to_direction(&self, s: f64, t: f64) -> Vector3 {
+        let phi = PI2 * (s - 0.5) + self.rotation;
+        let elevation = Self::elevation(t);
+        let horizontal = elevation.cos();
+        Float3::new(
+            -phi.sin() * horizontal,
+            elevation.sin(),
+            phi.cos() * horizontal,
+        )
+    }
+
+    /// (s, t)を含む画素の色
+    /// 補間すると選ぶ確率と明るさが画素の中で合わなくなり、小さな太陽などで白い点が出やすくなるので、
+    /// 画素の中では一定の値にする
+    fn texel(&self, s: f64, t: f64) -> Color {
+        let x = ((s * self.width as f64) as usize).min(self.width - 1);
+        let y = ((t * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x]
+    }
+
+    /// 画像上の確率密度を立体角あたりに直す
+    /// 画像全体(面積1)は全天球の4πに対応し、緯度のcosに比例して縮む
+    fn to_solid_angle(pdf: f64, t: f64) -> f64 {
+        let cos = Self::elevation(t).cos();
+        if cos <= 0.0 {
+            return 0.0;
+        }
+        pdf / (PI2 * PI * cos)
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, direction: Vector3) -> Color {
+        let (s, t) = self.direction_to_image(direction);
+        self.texel(s, t) * self.intensity
+    }
+
+    fn sample(&self) -> (Vector3, f64) {
+        let mut rng = rand::thread_rng();
+        let (s, t, pdf) = self.distribution.sample(rng.gen(), rng.gen());
+        (self.image_to_direction(s, t), Self::to_solid_angle(pdf, t))
+    }
+
+    fn pdf(&self, direction: Vector3) -> f64 {
+        let (s, t) = self.direction_to_image(direction);
+        Self::to_solid_angle(self.distribution.pdf(s, t), t)
+    }
+}
+
+fn luminance(c: Color) -> f64 {
+    c.x() * 0.2126 + c.y() * 0.7152 + c.z() * 0.0722
+}
+
+/// 0から1の範囲を等分した区間ごとに一定の値を持つ関数に比例する確率分布
+#[derive(Debug)]
+struct Distribution1D {
+    func: Vec<f64>,
+    /// 累積分布関数。cdf[i]が区間iの始まりでの値で、最後は1になる
+    cdf: Vec<f64>,
+    /// funcの0から1までの積分
+    integral: f64,
+}
+
+impl Distribution1D {
+    /// funcの合計が0の場合は一様な分布にする
+    fn new(func: Vec<f64>) -> Self {
+        let n = func.len() as f64;
+        let mut cdf = vec![0.0];
+        for f in &func {
+            cdf.push(cdf.last().unwrap() + f.max(0.0) / n);
+        }
+        let integral = *cdf.last().unwrap();
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n
+            };
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    /// 区間iでの確率密度
+    fn pdf_at(&self, i: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.func[i].max(0.0) / self.integral
+        } else {
+            1.0
+        }
+    }
+
+    /// 0から1の一様な乱数uから点を選び、その点、確率密度、区間の番号を返す
+    fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let last = self.func.len() - 1;
+        let i = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .saturating_sub(1)
+            .min(last);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0.0 {
+            (u - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+        let x = (i as f64 + offset.clamp(0.0, 1.0)) / self.func.len() as f64;
+        (x, self.pdf_at(i), i)
+    }
+}
+
+/// 0から1の正方形を格子に区切った区間ごとに一定の値を持つ関数に比例する確率分布
+/// 行(t)を周辺分布で選んでから、その行の中で列(s)を選ぶ
+#[derive(Debug)]
+struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// rows[t][s]の関数から作る
+    fn new(rows: Vec<Vec<f64>>) -> Self {
+        let rows: Vec<_> = rows.into_iter().map(Distribution1D::new).collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral).collect());
+        Self { rows, marginal }
+    }
+
+    /// 2つの一様な乱数から点(s, t)を選び、その点と確率密度を返す
+    fn sample(&self, u0: f64, u1: f64) -> (f64, f64, f64) {
+        let (t, pdf_t, row) = self.marginal.sample(u1);
+        let (s, pdf_s, _) = self.rows[row].sample(u0);
+        (s, t, pdf_s * pdf_t)
+    }
+
+    fn pdf(&self, s: f64, t: f64) -> f64 {
+        let index = |x: f64, n: usize| ((x * n as f64) as usize).min(n - 1);
+        let row = index(t, self.rows.len());
+        let column = index(s, self.rows[row].func.len());
+        self.marginal.pdf_at(row) * self.rows[row].pdf_at(column)
+    }
+}
@@ -7,15 +7,16 @@ use std::ops::Neg;
 use std::ops::Sub;
 
 use rand::Rng;
+use serde::Deserialize;
 
-use rand::prelude::*;
 use std::f64::consts::PI;
 /// Debug,Copy,Clone,PartialEqという機能を持ったpublicなFloat3という構造体を定義している
 /// Debug:構造体のインスタンスをデバッガで見やすい形式で出力できるようにする。例えば、println!("{:?}", instance); としてインスタンスの内容を確認できる
 /// Copy:このトレイトが実装されていると、構造体のインスタンスは「値によるコピー」（ビット単位のコピー）が可能になる。つまり、インスタンスを別の変数に代入すると、そのデータのコピーが作成される
 /// Clone:インスタンスを複製するためのメソッド（.clone()）を提供する
 /// PartialEq:== と != 演算子を使用して、インスタンス間の等価性比較を行うことができるようになる
-#[derive(Debug, Copy, Clone, PartialEq)]
+/// Deserialize:シーンファイルなどから[x, y, z]の配列として読み込めるようになる
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
 
 //ここで[f64; 3]にpubをつけることで、Float3構造体の内部の配列が外部からアクセス可能になる
 //実装例：let v = Float3([1.0, 2.0, 3.0]); println!("{:?}", v.0[0]); // 1.0
@@ -46,7 +47,7 @@ impl Float3 {
         Self([value; 3])
     }
 
-    /// Float3の各要素を取得するメソッド
+    // Float3の各要素を取得するメソッド
     // pub fn length(&self) -> f64 {
     //     let x = self.0[0];
     //     let y = self.0[1];
@@ -55,15 +56,6 @@ impl Float3 {
     //     (x * x + y * y + z * z).sqrt()
     // }
 
-    // 別の Float3 との和を計算するメソッド
-    pub fn add(&self, other: Float3) -> Float3 {
-        Float3([
-            self.0[0] + other.0[0],
-            self.0[1] + other.0[1],
-            self.0[2] + other.0[2],
-        ])
-    }
-
     pub fn sqrt(&self) -> Self {
         Self::from_iter(self.0.iter().map(|x| x.sqrt()))
     }
@@ -71,10 +63,6 @@ impl Float3 {
     //     self.0.iter().all(|x| x.abs() < EPS)
     // }
 
-    pub fn saturate(&self) -> Self {
-        Self::from_iter(self.0.iter().map(|x| x.min(1.0).max(0.0)))
-    }
-
     pub fn dot(&self, rhs: Self) -> f64 {
         self.0
             .iter()
@@ -125,48 +113,60 @@ impl Float3 {
         Self::new(0.0, 0.0, 1.0)
     }
 
-    pub fn random() -> Self {
-        Self::new(random::<f64>(), random::<f64>(), random::<f64>())
-    }
-
-    pub fn random_full() -> Self {
-        Self::full(random::<f64>())
-    }
-
-    pub fn random_limit(min: f64, max: f64) -> Self {
-        Self::from_iter(Self::random().0.iter().map(|x| min + x * (max - min)))
-    }
-
-    pub fn at(origin: Vector3, direction: Point3) -> Point3 {
-        origin + direction * 2.0
-    }
-
-    pub fn random_in_unit_sphere() -> Float3 {
-        loop {
-            let point = Self::random_limit(-1.0, 1.0);
-            if point.length_squared() < 1.0 {
-                return point;
-            }
-        }
-    }
-
     pub fn randpm_unit_vector() -> Float3 {
         let mut rng = rand::thread_rng();
         let a: f64 = rng.gen_range(0.0..2.0 * PI); //Rng::gen(0.0, 2.0 * PI);
         let z: f64 = rng.gen_range(-1.0..1.0);
         let num: f64 = 1.0 - z * z;
         let r: f64 = num.sqrt();
-        return Float3::new(r * a.cos(), r * a.sin(), z);
+        Float3::new(r * a.cos(), r * a.sin(), z)
     }
 
-    pub fn gamma(&self, factor: f64) -> Float3 {
-        let recip = factor.recip();
-        Self::from_iter(self.0.iter().map(|x| x.powf(recip)))
+    /// 正規化済みのnormalの側の半球から、cosに比例した確率で方向を選ぶ
+    pub fn random_cosine_direction(normal: Self) -> Float3 {
+        let mut rng = rand::thread_rng();
+        let phi: f64 = rng.gen_range(0.0..2.0 * PI);
+        let r2: f64 = rng.gen();
+        let r = r2.sqrt();
+        let (u, v) = normal.orthonormal_basis();
+        (u * (r * phi.cos()) + v * (r * phi.sin()) + normal * (1.0 - r2).sqrt()).normalize()
     }
 
     pub fn reflect(&self, normal: Self) -> Float3 {
         *self - normal * 2.0 * self.dot(normal)
     }
+
+    /// スネルの法則による屈折方向。selfとnormalは正規化済みで、normalはselfと逆向きとする
+    /// etaは入射側の屈折率 / 透過側の屈折率。全反射する場合はNone
+    pub fn refract(&self, normal: Self, eta: f64) -> Option<Float3> {
+        let cos_i = (-*self).dot(normal).min(1.0);
+        let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+        if sin2_t > 1.0 {
+            return None;
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        Some(*self * eta + normal * (eta * cos_i - cos_t))
+    }
+
+    pub fn exp(&self) -> Self {
+        Self::from_iter(self.0.iter().map(|x| x.exp()))
+    }
+
+    /// 正規化済みのselfと直交する2つの単位ベクトル(u, v)を返す。(u, v, self)は右手系になる
+    /// Duff et al. "Building an Orthonormal Basis, Revisited" (2017) の方法
+    pub fn orthonormal_basis(&self) -> (Vector3, Vector3) {
+        let sign = 1.0_f64.copysign(self.z());
+        let a = -1.0 / (sign + self.z());
+        let b = self.x() * self.y() * a;
+        (
+            Float3::new(
+                1.0 + sign * self.x() * self.x() * a,
+                sign * b,
+                -sign * self.x(),
+            ),
+            Float3::new(b, sign + self.y() * self.y() * a, -self.y()),
+        )
+    }
 }
 
 impl FromIterator<f64> for Float3 {
@@ -0,0 +1,149 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use rayon::prelude::*;
+
+use super::camera::Camera;
+use super::float3::{Color, Float3};
+use super::shape::SimpleScene;
+
+/// レンダリング結果を保持するメモリ上の画像バッファ
+/// ウィンドウの有無に関係なく同じ描画ループを使えるように、色はf64のまま保持する
+/// サンプルは合計を蓄積していき、読み出すときにサンプル数で割る
+pub struct FrameBuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+    sample_count: usize,
+}
+
+impl FrameBuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Float3::zero(); (width * height) as usize],
+            sample_count: 0,
+        }
+    }
+
+    /// これまでに蓄積した1ピクセルあたりのサンプル数
+    pub fn sample_count(&self) -> usize {
+        self.sample_count
+    }
+
+    /// 蓄積したサンプルを捨てる。カメラやシーンが変わったときに使う
+    pub fn clear(&mut self) {
+        self.pixels.fill(Float3::zero());
+        self.sample_count = 0;
+    }
+
+    /// 蓄積したサンプルを捨ててから、各ピクセルをsample回traceした平均を求める
+    pub fn render(&mut self, scene: &SimpleScene, camera: &Camera, sample: usize, depth: usize) {
+        self.clear();
+        self.accumulate(scene, camera, sample, depth);
+    }
+
+    /// 各ピクセルについてcamera.rayで生成したレイをsample回traceし、その合計をこれまでの結果に足す
+    pub fn accumulate(
+        &mut self,
+        scene: &SimpleScene,
+        camera: &Camera,
+        sample: usize,
+        depth: usize,
+    ) {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let (cone_width, cone_spread) = camera.pixel_cone(self.height);
+        self.pixels
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, pixel)| {
+                let u = (i % width) as f64 / width as f64;
+                let v = 1.0 - (i / width) as f64 / height as f64;
+
+                //レンズ上の点はサンプルごとに変わるので、レイもサンプルごとに作る
+                let color = (0..sample)
+                    .into_par_iter() // 並列イテレータ
+                    .fold(Float3::zero, |acc, _| {
+                        acc + camera.ray(u, v).map_or(Float3::zero(), |ray| {
+                            scene.trace(ray.with_cone(cone_width, cone_spread), depth)
+                        })
+                    })
+                    .reduce(Float3::zero, |acc, val| acc + val);
+                *pixel += color;
+            });
+        self.sample_count += sample;
+    }
+
+    /// 蓄積したサンプルの平均
+    fn colors(&self) -> impl Iterator<Item = Color> + '_ {
+        let scale = (self.sample_count.max(1) as f64).recip();
+        self.pixels.iter().map(move |&color| color * scale)
+    }
+
+    /// 色を8bitのRGBAに変換する。範囲外の値はu8へのキャストで0と255に丸められる
+    pub fn to_rgba8(color: Color) -> [u8; 4] {
+        [
+            (color.x() * 255.0) as u8,
+            (color.y() * 255.0) as u8,
+            (color.z() * 255.0) as u8,
+            255,
+        ]
+    }
+
+    /// pixelsクレートのフレームなど、RGBA8のバッファへ書き出す
+    pub fn copy_to_rgba8(&self, frame: &mut [u8]) {
+        frame
+            .chunks_exact_mut(4)
+            .zip(self.colors())
+            .for_each(|(dst, color)| dst.copy_from_slice(&Self::to_rgba8(color)));
+    }
+
+    fn to_rgb8(&self) -> Vec<u8> {
+        self.colors()
+            .flat_map(|color| {
+                let [r, g, b, _] = Self::to_rgba8(color);
+                [r, g, b]
+            })
+            .collect()
+    }
+
+    /// 拡張子(.png / .ppm)に応じた形式で画像ファイルに保存する
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("png") => self.save_png(path),
+            Some("ppm") => self.save_ppm(path),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "unsupported image format: {} (expected .png or .ppm)",
+                    path.display()
+                ),
+            )),
+        }
+    }
+
+    fn save_png(&self, path: &Path) -> io::Result<()> {
+        image::save_buffer(
+            path,
+            &self.to_rgb8(),
+            self.width,
+            self.height,
+            image::ColorType::Rgb8,
+        )
+        .map_err(io::Error::other)
+    }
+
+    fn save_ppm(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "P6\n{} {}\n255", self.width, self.height)?;
+        writer.write_all(&self.to_rgb8())?;
+        writer.flush()
+    }
+}
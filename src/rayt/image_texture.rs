@@ -0,0 +1,264 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use image::codecs::hdr::HdrDecoder;
+use image::ImageError;
+use serde::Deserialize;
+
+use super::float3::{Color, Float3, Point3};
+use super::texture::Texture;
+
+/// 画像を読むときのフィルタリング
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    /// 最も近いテクセル
+    Nearest,
+    /// 周りの4つのテクセルの線形補間
+    Bilinear,
+    /// レイの太さに合ったミップマップの2つのレベルでbilinearで読み、その間を補間する
+    #[default]
+    Trilinear,
+}
+
+/// テクスチャ座標が0から1の範囲の外に出たときの扱い
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Wrap {
+    /// 繰り返す
+    #[default]
+    Repeat,
+    /// 端のテクセルを伸ばす
+    Clamp,
+    /// 1つおきに反転して繰り返す
+    Mirror,
+}
+
+/// 画像の値の色空間
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    /// sRGBで符号化された色の画像。読み込むときにリニアに変換する
+    #[default]
+    Srgb,
+    /// 粗さや法線などの数値の画像。値をそのまま使う
+    Linear,
+}
+
+/// ミップマップの1つのレベル。pixelsは上の行から順に並べる
+#[derive(Debug)]
+struct MipLevel {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl MipLevel {
+    /// 縦横を半分にした次のレベル。奇数の場合は端のテクセルを2回使う
+    fn downsample(&self) -> Self {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (x0, y0) = ((x * 2).min(self.width - 1), (y * 2).min(self.height - 1));
+                let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+                let sum = self.pixels[y0 * self.width + x0]
+                    + self.pixels[y0 * self.width + x1]
+                    + self.pixels[y1 * self.width + x0]
+                    + self.pixels[y1 * self.width + x1];
+                pixels.push(sum * 0.25);
+            }
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+/// 画像のテクスチャ。値はリニアな浮動小数点数で持つ
+/// 画像の上端がv = 1、下端がv = 0になる
+#[derive(Debug)]
+pub struct ImageTexture {
+    /// levels[0]が元の画像で、縦横を半分ずつにしながら1x1まで並べる
+    levels: Vec<MipLevel>,
+    filter: Filter,
+    wrap: Wrap,
+}
+
+impl ImageTexture {
+    /// 幅width、高さheightで、上の行から順に並べたリニアな色からテクスチャを作る
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert!(width > 0 && height > 0, "image must not be empty");
+        assert_eq!(pixels.len(), width * height, "image size mismatch");
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            pixels,
+        }];
+        while let Some(last) = levels.last().filter(|l| l.width > 1 || l.height > 1) {
+            levels.push(last.downsample());
+        }
+        Self {
+            levels,
+            filter: Filter::default(),
+            wrap: Wrap::default(),
+        }
+    }
+
+    /// PNG、JPEG、HDR(Radiance)、OpenEXRの画像を読み込む
+    /// HDRとOpenEXRの画像は元からリニアなので、color_spaceに関係なくそのまま使う
+    pub fn load(path: &Path, color_space: ColorSpace) -> Result<Self, ImageError> {
+        let (width, height, pixels) = load_pixels(path, color_space)?;
+        Ok(Self::new(width, height, pixels))
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_wrap(mut self, wrap: Wrap) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    /// テクセルの整数の座標を、wrapに従って0からsize - 1の範囲に収める
+    fn wrap_index(&self, i: i64, size: usize) -> usize {
+        let n = size as i64;
+        let i = match self.wrap {
+            Wrap::Repeat => i.rem_euclid(n),
+            Wrap::Clamp => i.clamp(0, n - 1),
+            Wrap::Mirror => {
+                let i = i.rem_euclid(2 * n);
+                if i < n {
+                    i
+                } else {
+                    2 * n - 1 - i
+                }
+            }
+        };
+        i as usize
+    }
+
+    fn texel(&self, level: &MipLevel, x: i64, y: i64) -> Color {
+        let x = self.wrap_index(x, level.width);
+        let y = self.wrap_index(y, level.height);
+        level.pixels[y * level.width + x]
+    }
+
+    /// テクセルの座標(テクセルの中心が整数 + 0.5)
+    fn texel_coord(level: &MipLevel, u: f64, v: f64) -> (f64, f64) {
+        (u * level.width as f64, (1.0 - v) * level.height as f64)
+    }
+
+    fn nearest(&self, level: &MipLevel, u: f64, v: f64) -> Color {
+        let (x, y) = Self::texel_coord(level, u, v);
+        self.texel(level, x.floor() as i64, y.floor() as i64)
+    }
+
+    fn bilinear(&self, level: &MipLevel, u: f64, v: f64) -> Color {
+        let (x, y) = Self::texel_coord(level, u, v);
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = self.texel(level, x0, y0) * (1.0 - fx) + self.texel(level, x0 + 1, y0) * fx;
+        let bottom =
+            self.texel(level, x0, y0 + 1) * (1.0 - fx) + self.texel(level, x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    /// レイの太さがテクセル1つ分になるレベルを選び、前後のレベルの間を補間する
+    fn trilinear(&self, u: f64, v: f64, footprint: f64) -> Color {
+        let texels = footprint * self.width().max(self.height()) as f64;
+        let lod = if texels > 1.0 { texels.log2() } else { 0.0 };
+        let last = self.levels.len() - 1;
+        if lod >= last as f64 {
+            return self.bilinear(&self.levels[last], u, v);
+        }
+        let level = lod.floor() as usize;
+        let t = lod - level as f64;
+        let fine = self.bilinear(&self.levels[level], u, v);
+        if t <= 0.0 {
+            return fine;
+        }
+        fine * (1.0 - t) + self.bilinear(&self.levels[level + 1], u, v) * t
+    }
+}
+
+/// 画像を読み込み、幅と高さと、上の行から順に並べたリニアな色を返す
+/// HDRとOpenEXRの画像は元からリニアなので、color_spaceに関係なくそのまま使う
+pub fn load_pixels(
+    path: &Path,
+    color_space: ColorSpace,
+) -> Result<(usize, usize, Vec<Color>), ImageError> {
+    let has_extension = |name: &str| {
+        path.extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(name))
+    };
+    if has_extension("hdr") {
+        return load_hdr(path);
+    }
+    let color_space = if has_extension("exr") {
+        ColorSpace::Linear
+    } else {
+        color_space
+    };
+    let image = image::open(path)?.into_rgb32f();
+    let decode = |c: f32| match color_space {
+        ColorSpace::Srgb => srgb_to_linear(c as f64),
+        ColorSpace::Linear => c as f64,
+    };
+    let pixels = image
+        .pixels()
+        .map(|p| Float3::new(decode(p[0]), decode(p[1]), decode(p[2])))
+        .collect();
+    Ok((image.width() as usize, image.height() as usize, pixels))
+}
+
+/// image::openではHDRの画像が8bitに変換されてしまうので、直接デコーダーを使う
+fn load_hdr(path: &Path) -> Result<(usize, usize, Vec<Color>), ImageError> {
+    let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+    let meta = decoder.metadata();
+    let pixels = decoder
+        .read_image_hdr()?
+        .into_iter()
+        .map(|p| Float3::new(p[0] as f64, p[1] as f64, p[2] as f64))
+        .collect();
+    Ok((meta.width as usize, meta.height as usize, pixels))
+}
+
+/// sRGBで符号化された値(0から1)をリニアな値にする
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.filtered(u, v, p, 0.0)
+    }
+
+    fn filtered(&self, u: f64, v: f64, _p: Point3, footprint: f64) -> Color {
+        match self.filter {
+            Filter::Nearest => self.nearest(&self.levels[0], u, v),
+            Filter::Bilinear => self.bilinear(&self.levels[0], u, v),
+            Filter::Trilinear => self.trilinear(u, v, footprint),
+        }
+    }
+}
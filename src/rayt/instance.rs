@@ -0,0 +1,138 @@
+use std::sync::Arc;
+
+use super::aabb::Aabb;
+use super::float3::{Float3, Point3, Vector3};
+use super::matrix::Matrix4;
+use super::motion::Keyframes;
+use super::ray::Ray;
+use super::shape::{HitInfo, Shape};
+
+/// 形状をアフィン変換して配置するインスタンス
+/// 形状はArcで共有するので、同じメッシュを複製せずに何か所にも置ける
+/// 交差判定ではレイを物体の座標系に変換し、当たった点と法線をワールドの座標系に戻す
+#[derive(Debug)]
+pub struct Instance {
+    object: Arc<dyn Shape>,
+    to_world: Matrix4,
+    to_object: Matrix4,
+    /// 変換の後に加える、時刻に応じた移動量
+    motion: Option<Keyframes>,
+}
+
+impl Instance {
+    /// to_worldは物体の座標系からワールドの座標系への変換。逆行列を持つこと
+    pub fn new(object: Arc<dyn Shape>, to_world: Matrix4) -> Self {
+        let to_object = to_world
+            .inverse()
+            .expect("instance transform must be invertible");
+        Self {
+            object,
+            to_world,
+            to_object,
+            motion: None,
+        }
+    }
+
+    /// 変換した後の位置を、キーフレームの移動量だけ時刻に応じてずらす
+    pub fn with_motion(mut self, motion: Keyframes) -> Self {
+        self.motion = Some(motion);
+        self
+    }
+
+    fn offset(&self, time: f64) -> Vector3 {
+        self.motion
+            .as_ref()
+            .map_or(Float3::zero(), |motion| motion.at(time))
+    }
+
+    /// ワールドのレイを物体の座標系に変換する。tの値は変換の前後で変わらない
+    /// レイの太さは平均の拡大率(行列式の3乗根)で変換する
+    fn to_object_ray(&self, ray: &Ray) -> Ray {
+        let origin = ray.origin - self.offset(ray.time);
+        let scale = self.to_object.determinant3().abs().cbrt();
+        Ray::with_time(
+            self.to_object.transform_point(origin),
+            self.to_object.transform_vector(ray.direction),
+            ray.time,
+        )
+        .with_cone(ray.width * scale, ray.spread * scale)
+    }
+}
+
+impl Shape for Instance {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitInfo> {
+        let mut hit = self.object.hit(&self.to_object_ray(ray), t_min, t_max)?;
+        hit.p = self.to_world.transform_point(hit.p) + self.offset(ray.time);
+        //法線は逆転置行列で変換する。nがレイの来た側を向いていることは変換しても変わらない
+        hit.n = self
+            .to_object
+            .transform_normal_by_inverse(hit.n)
+            .normalize();
+        Some(hit)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let bbox = self.object.bounding_box();
+        if bbox.is_empty() {
+            return bbox;
+        }
+        //物体のボックスの8つの頂点を変換して囲む
+        let corners = (0..8).map(|i| {
+            Float3::new(
+                if i & 1 == 0 {
+                    bbox.min.x()
+                } else {
+                    bbox.max.x()
+                },
+                if i & 2 == 0 {
+                    bbox.min.y()
+                } else {
+                    bbox.max.y()
+                },
+                if i & 4 == 0 {
+                    bbox.min.z()
+                } else {
+                    bbox.max.z()
+                },
+            )
+        });
+        let bbox = corners.fold(Aabb::empty(), |acc, p| {
+            acc.grow(self.to_world.transform_point(p))
+        });
+        match &self.motion {
+            //キーの間は線形に動くので、各キーの位置に移したボックスを囲めば動き全体を囲める
+            Some(motion) => motion.points().fold(Aabb::empty(), |acc, offset| {
+                acc.surrounding(&Aabb::new(bbox.min + offset, bbox.max + offset))
+            }),
+            None => bbox,
+        }
+    }
+
+    /// 角度を保つ変換の場合だけ、中の光源を同じ変換をしたインスタンスとして直接サンプリングする
+    /// (それ以外の変換では立体角あたりの確率密度が変わってしまう)
+    /// 動くインスタンスは光源としてサンプリングしない
+    fn collect_lights(&self, lights: &mut Vec<Box<dyn Shape>>) {
+        if !self.to_world.is_similarity() || self.motion.is_some() {
+            return;
+        }
+        let mut object_lights = Vec::new();
+        self.object.collect_lights(&mut object_lights);
+        for light in object_lights {
+            lights.push(Box::new(Instance::new(Arc::from(light), self.to_world)));
+        }
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vector3) -> f64 {
+        self.object.pdf_value(
+            self.to_object.transform_point(origin),
+            self.to_object.transform_vector(direction),
+        )
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vector3 {
+        let direction = self
+            .object
+            .random_direction(self.to_object.transform_point(origin));
+        self.to_world.transform_vector(direction)
+    }
+}
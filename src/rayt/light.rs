@@ -0,0 +1,145 @@
+use std::fmt::Debug;
+
+use super::float3::{Color, Point3, Vector3};
+
+/// 光源からpに届く光
+pub struct LightSample {
+    /// pから光源への方向(正規化済み)
+    pub wi: Vector3,
+    /// 光源までの距離。シャドウレイはここまで調べる
+    pub distance: f64,
+    /// pでwiに垂直な面が受ける放射照度
+    pub irradiance: Color,
+}
+
+/// 大きさを持たない光源
+/// 位置や方向が1つに決まるので、BSDFのサンプリングでは当たらず、直接サンプリングだけで照らす
+pub trait Light: Sync + Send + Debug {
+    /// pに届く光。pに光が届かない場合はNone
+    fn sample(&self, p: Point3) -> Option<LightSample>;
+}
+
+/// 全方向に同じ強さで光る点光源。明るさは距離の2乗に反比例する
+#[derive(Debug, Clone)]
+pub struct PointLight {
+    position: Point3,
+    /// 放射強度(単位立体角あたりの光の強さ)
+    intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance2 = to_light.length_squared();
+        if distance2 <= 0.0 {
+            return None;
+        }
+        let distance = distance2.sqrt();
+        Some(LightSample {
+            wi: to_light / distance,
+            distance,
+            irradiance: self.intensity / distance2,
+        })
+    }
+}
+
+/// directionの方向を照らすスポットライト
+/// 中心からinnerの角度までは点光源と同じ明るさで、outerの角度に向けて滑らかに暗くなる
+#[derive(Debug, Clone)]
+pub struct SpotLight {
+    position: Point3,
+    /// 照らす方向(正規化済み)
+    direction: Vector3,
+    intensity: Color,
+    cos_inner: f64,
+    cos_outer: f64,
+}
+
+impl SpotLight {
+    /// inner、outerは照らす方向からの角度(度)
+    pub fn new(
+        position: Point3,
+        direction: Vector3,
+        intensity: Color,
+        inner: f64,
+        outer: f64,
+    ) -> Self {
+        let outer = outer.clamp(0.0, 180.0);
+        let inner = inner.clamp(0.0, outer);
+        Self {
+            position,
+            direction: direction.normalize(),
+            intensity,
+            cos_inner: inner.to_radians().cos(),
+            cos_outer: outer.to_radians().cos(),
+        }
+    }
+
+    /// 照らす方向となす角の余弦がcosの方向の明るさの割合
+    fn falloff(&self, cos: f64) -> f64 {
+        if cos >= self.cos_inner {
+            return 1.0;
+        }
+        if cos <= self.cos_outer {
+            return 0.0;
+        }
+        let t = (cos - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance2 = to_light.length_squared();
+        if distance2 <= 0.0 {
+            return None;
+        }
+        let distance = distance2.sqrt();
+        let wi = to_light / distance;
+        let falloff = self.falloff(-wi.dot(self.direction));
+        (falloff > 0.0).then(|| LightSample {
+            wi,
+            distance,
+            irradiance: self.intensity * (falloff / distance2),
+        })
+    }
+}
+
+/// 太陽のように無限に遠くから平行に届く光
+#[derive(Debug, Clone)]
+pub struct DirectionalLight {
+    /// 光源への方向(光の進む向きの逆、正規化済み)
+    to_light: Vector3,
+    /// 光に垂直な面が受ける放射照度
+    irradiance: Color,
+}
+
+impl DirectionalLight {
+    /// directionは光の進む向き
+    pub fn new(direction: Vector3, irradiance: Color) -> Self {
+        Self {
+            to_light: -direction.normalize(),
+            irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: Point3) -> Option<LightSample> {
+        Some(LightSample {
+            wi: self.to_light,
+            distance: f64::INFINITY,
+            irradiance: self.irradiance,
+        })
+    }
+}
@@ -1,4 +1,3 @@
 pub use std::f64::consts::FRAC_1_PI;
 pub use std::f64::consts::PI;
 pub const PI2: f64 = PI * 2.0;
-pub const EPS: f64 = 1e-6;
@@ -0,0 +1,131 @@
+use std::ops::Mul;
+
+use super::float3::{Float3, Point3, Vector3};
+use super::quaternion::Quaternion;
+
+/// アフィン変換に使う4x4の行列。m[行][列]で、点は列ベクトルとして左から掛ける
+/// 最後の行は常に(0, 0, 0, 1)とする
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Matrix4(pub [[f64; 4]; 4]);
+
+impl Matrix4 {
+    pub const fn identity() -> Self {
+        Self([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translation(t: Vector3) -> Self {
+        let mut m = Self::identity();
+        for i in 0..3 {
+            m.0[i][3] = t.0[i];
+        }
+        m
+    }
+
+    pub fn scale(s: Vector3) -> Self {
+        let mut m = Self::identity();
+        for i in 0..3 {
+            m.0[i][i] = s.0[i];
+        }
+        m
+    }
+
+    /// 単位四元数による回転
+    pub fn rotation(q: Quaternion) -> Self {
+        let r = q.normalize().to_matrix();
+        let mut m = Self::identity();
+        for (i, row) in r.iter().enumerate() {
+            m.0[i][..3].copy_from_slice(row);
+        }
+        m
+    }
+
+    /// 拡大縮小、回転、平行移動の順に変換する行列
+    pub fn from_trs(translation: Vector3, rotation: Quaternion, scale: Vector3) -> Self {
+        Self::translation(translation) * Self::rotation(rotation) * Self::scale(scale)
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        let m = &self.0;
+        Float3::from_iter(
+            (0..3).map(|i| m[i][0] * p.x() + m[i][1] * p.y() + m[i][2] * p.z() + m[i][3]),
+        )
+    }
+
+    /// 方向ベクトルの変換。平行移動は影響しない
+    pub fn transform_vector(&self, v: Vector3) -> Vector3 {
+        let m = &self.0;
+        Float3::from_iter((0..3).map(|i| m[i][0] * v.x() + m[i][1] * v.y() + m[i][2] * v.z()))
+    }
+
+    /// 法線の変換。この行列の逆行列に対して呼び、転置を掛ける
+    /// (法線は変換の逆転置行列で変換すると面に垂直なまま保たれる)
+    pub fn transform_normal_by_inverse(&self, n: Vector3) -> Vector3 {
+        let m = &self.0;
+        Float3::from_iter((0..3).map(|i| m[0][i] * n.x() + m[1][i] * n.y() + m[2][i] * n.z()))
+    }
+
+    /// 左上3x3の行列式
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.0;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// アフィン変換の逆行列。逆行列が無い(どこかの軸の拡大率が0)場合はNone
+    pub fn inverse(&self) -> Option<Self> {
+        let det = self.determinant3();
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+        let m = &self.0;
+        let recip = det.recip();
+        //左上3x3は余因子行列で逆行列を求める
+        let mut inv = Self::identity();
+        for i in 0..3 {
+            for j in 0..3 {
+                let (r0, r1) = ((j + 1) % 3, (j + 2) % 3);
+                let (c0, c1) = ((i + 1) % 3, (i + 2) % 3);
+                inv.0[i][j] = (m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]) * recip;
+            }
+        }
+        //平行移動は -R^-1 * t
+        let t = inv.transform_vector(Float3::new(m[0][3], m[1][3], m[2][3]));
+        for i in 0..3 {
+            inv.0[i][3] = -t.0[i];
+        }
+        Some(inv)
+    }
+
+    /// 角度を保つ変換(回転、平行移動、全ての軸で同じ拡大率の拡大縮小、鏡映の組み合わせ)かどうか
+    pub fn is_similarity(&self) -> bool {
+        let m = &self.0;
+        let column = |j: usize| Float3::new(m[0][j], m[1][j], m[2][j]);
+        let (a, b, c) = (column(0), column(1), column(2));
+        let scale = a.length_squared();
+        let eps = 1e-9 * scale;
+        (b.length_squared() - scale).abs() <= eps
+            && (c.length_squared() - scale).abs() <= eps
+            && a.dot(b).abs() <= eps
+            && b.dot(c).abs() <= eps
+            && c.dot(a).abs() <= eps
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self::Output {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.0[i][k] * rhs.0[k][j]).sum();
+            }
+        }
+        Self(m)
+    }
+}
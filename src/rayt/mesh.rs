@@ -0,0 +1,342 @@
+use std::sync::Arc;
+
+use super::aabb::Aabb;
+use super::bvh::Bvh;
+use super::float3::{Float3, Point3, Vector3};
+use super::ray::Ray;
+use super::render::Material;
+use super::shape::{HitInfo, Shape};
+
+/// レイと三角形の交差判定
+/// Woop, Benthin, Wald "Watertight Ray/Triangle Intersection" (2013) の方法で、
+/// 隣り合う三角形の辺の上をレイが通っても隙間ができないように判定する
+/// 交差した場合は(t, 重心座標)を返す。重心座標はそれぞれp0, p1, p2の重み
+fn intersect_triangle(
+    ray: &Ray,
+    p0: Point3,
+    p1: Point3,
+    p2: Point3,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, [f64; 3])> {
+    let d = ray.direction;
+    //レイの方向の成分が最も大きい軸をzとして座標系を取り直す
+    let kz = (0..3)
+        .max_by(|&a, &b| d.0[a].abs().total_cmp(&d.0[b].abs()))
+        .unwrap();
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    //三角形の向き(巻き方向)が変わらないように入れ替える
+    if d.0[kz] < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+    if d.0[kz] == 0.0 {
+        return None;
+    }
+    let sx = d.0[kx] / d.0[kz];
+    let sy = d.0[ky] / d.0[kz];
+    let sz = 1.0 / d.0[kz];
+
+    //レイの原点を原点、レイの方向を+zとする空間に頂点を移す
+    let a = p0 - ray.origin;
+    let b = p1 - ray.origin;
+    let c = p2 - ray.origin;
+    let ax = a.0[kx] - sx * a.0[kz];
+    let ay = a.0[ky] - sy * a.0[kz];
+    let bx = b.0[kx] - sx * b.0[kz];
+    let by = b.0[ky] - sy * b.0[kz];
+    let cx = c.0[kx] - sx * c.0[kz];
+    let cy = c.0[ky] - sy * c.0[kz];
+
+    //辺関数。符号が揃っていれば原点(=レイ)が三角形の内側にある
+    let u = cx * by - cy * bx;
+    let v = ax * cy - ay * cx;
+    let w = bx * ay - by * ax;
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+    let det = u + v + w;
+    if det == 0.0 {
+        return None;
+    }
+
+    let az = sz * a.0[kz];
+    let bz = sz * b.0[kz];
+    let cz = sz * c.0[kz];
+    let t = (u * az + v * bz + w * cz) / det;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some((t, [u / det, v / det, w / det]))
+}
+
+fn triangle_area(p0: Point3, p1: Point3, p2: Point3) -> f64 {
+    (p1 - p0).cross(p2 - p0).length() * 0.5
+}
+
+/// 三角形のUVでの面積と実際の面積から、長さ1あたりのテクスチャ座標の変化量を求める
+fn uv_density(uv_area: f64, area: f64) -> f64 {
+    if area > 0.0 {
+        (uv_area / area).sqrt()
+    } else {
+        0.0
+    }
+}
+
+fn triangle_bounding_box(p0: Point3, p1: Point3, p2: Point3) -> Aabb {
+    //軸に平行な三角形は厚みが0になるので少しだけ広げる
+    Aabb::from_points(p0, p1).grow(p2).pad(1e-4)
+}
+
+/// 単体の三角形
+#[derive(Debug)]
+pub struct Triangle {
+    p0: Point3,
+    p1: Point3,
+    p2: Point3,
+    n: Vector3,
+    material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    /// 法線は(p1 - p0) x (p2 - p0)の向きになる
+    pub fn new(p0: Point3, p1: Point3, p2: Point3, material: Arc<dyn Material>) -> Self {
+        Self {
+            p0,
+            p1,
+            p2,
+            n: (p1 - p0).cross(p2 - p0).normalize(),
+            material,
+        }
+    }
+}
+
+impl Shape for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitInfo> {
+        let (t, b) = intersect_triangle(ray, self.p0, self.p1, self.p2, t_min, t_max)?;
+        //UVを持たないので、p1とp2の重みをそのまま使う。UVでの面積は1/2になる
+        Some(HitInfo::new(
+            t,
+            ray.at(t),
+            self.n,
+            (b[1], b[2]),
+            uv_density(0.5, triangle_area(self.p0, self.p1, self.p2)),
+            ray,
+            Arc::clone(&self.material),
+        ))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        triangle_bounding_box(self.p0, self.p1, self.p2)
+    }
+}
+
+/// 三角形メッシュの頂点データ
+/// normalsとuvsは空にするか、positionsと同じ数の要素を持たせる
+#[derive(Debug)]
+pub struct MeshData {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vector3>,
+    pub uvs: Vec<[f64; 2]>,
+    pub indices: Vec<[usize; 3]>,
+}
+
+impl MeshData {
+    fn vertices(&self, face: usize) -> (Point3, Point3, Point3) {
+        let [i0, i1, i2] = self.indices[face];
+        (self.positions[i0], self.positions[i1], self.positions[i2])
+    }
+
+    /// 面の法線を頂点ごとに面積で重み付けして平均し、スムーズシェーディング用の法線を作る
+    pub fn compute_normals(&mut self) {
+        let mut normals = vec![Float3::zero(); self.positions.len()];
+        for face in 0..self.indices.len() {
+            let (p0, p1, p2) = self.vertices(face);
+            //外積の長さは面積の2倍なので、正規化しなければそのまま面積の重みになる
+            let n = (p1 - p0).cross(p2 - p0);
+            for &i in &self.indices[face] {
+                normals[i] += n;
+            }
+        }
+        self.normals = normals
+            .into_iter()
+            .map(|n| {
+                if n.length_squared() > 0.0 {
+                    n.normalize()
+                } else {
+                    n
+                }
+            })
+            .collect();
+    }
+}
+
+/// メッシュ内の1つの三角形。頂点データはメッシュ全体で共有する
+#[derive(Debug)]
+struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    face: usize,
+    material: Arc<dyn Material>,
+}
+
+impl Shape for MeshTriangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitInfo> {
+        let (p0, p1, p2) = self.mesh.vertices(self.face);
+        let (t, b) = intersect_triangle(ray, p0, p1, p2, t_min, t_max)?;
+        let [i0, i1, i2] = self.mesh.indices[self.face];
+        //UVが無いメッシュでは、Triangleと同じく重心座標をそのまま使う
+        let (uv, uv_area) = if self.mesh.uvs.is_empty() {
+            ((b[1], b[2]), 0.5)
+        } else {
+            let (uv0, uv1, uv2) = (self.mesh.uvs[i0], self.mesh.uvs[i1], self.mesh.uvs[i2]);
+            let (e1, e2) = (
+                [uv1[0] - uv0[0], uv1[1] - uv0[1]],
+                [uv2[0] - uv0[0], uv2[1] - uv0[1]],
+            );
+            (
+                (
+                    uv0[0] * b[0] + uv1[0] * b[1] + uv2[0] * b[2],
+                    uv0[1] * b[0] + uv1[1] * b[1] + uv2[1] * b[2],
+                ),
+                (e1[0] * e2[1] - e1[1] * e2[0]).abs() * 0.5,
+            )
+        };
+        //表裏は面の向き(頂点の巻き方向)で決める
+        let mut hit = HitInfo::new(
+            t,
+            ray.at(t),
+            (p1 - p0).cross(p2 - p0).normalize(),
+            uv,
+            uv_density(uv_area, triangle_area(p0, p1, p2)),
+            ray,
+            Arc::clone(&self.material),
+        );
+        if !self.mesh.normals.is_empty() {
+            //頂点の法線を重心座標で補間し、面の法線と同じ側に向ける
+            //向きの違う法線が打ち消し合って長さが0になった場合は面の法線のままにする
+            let n = self.mesh.normals[i0] * b[0]
+                + self.mesh.normals[i1] * b[1]
+                + self.mesh.normals[i2] * b[2];
+            if n.length_squared() > 0.0 {
+                let n = n.normalize();
+                hit.n = if hit.front_face { n } else { -n };
+            }
+        }
+        Some(hit)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let (p0, p1, p2) = self.mesh.vertices(self.face);
+        triangle_bounding_box(p0, p1, p2)
+    }
+}
+
+/// インデックス付きの三角形メッシュ
+/// 頂点・法線・UVのバッファとマテリアルを全ての面で共有し、面はBVHで管理する
+#[derive(Debug)]
+pub struct TriangleMesh {
+    triangles: Bvh,
+}
+
+impl TriangleMesh {
+    pub fn new(mesh: MeshData, material: Arc<dyn Material>) -> Self {
+        assert!(
+            mesh.normals.is_empty() || mesh.normals.len() == mesh.positions.len(),
+            "mesh must have one normal per vertex"
+        );
+        assert!(
+            mesh.uvs.is_empty() || mesh.uvs.len() == mesh.positions.len(),
+            "mesh must have one uv per vertex"
+        );
+        assert!(
+            mesh.indices
+                .iter()
+                .flatten()
+                .all(|&i| i < mesh.positions.len()),
+            "mesh index out of range"
+        );
+        let mesh = Arc::new(mesh);
+        let triangles = Bvh::new(
+            (0..mesh.indices.len())
+                .map(|face| {
+                    Box::new(MeshTriangle {
+                        mesh: Arc::clone(&mesh),
+                        face,
+                        material: Arc::clone(&material),
+                    }) as Box<dyn Shape>
+                })
+                .collect(),
+        );
+        Self { triangles }
+    }
+}
+
+impl Shape for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitInfo> {
+        self.triangles.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.triangles.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rayt::render::Lambertian;
+
+    /// x = 0の稜線で折れ曲がった、2枚の三角形からなる屋根。面の法線は(∓1, 1, 0) / √2
+    fn roof() -> MeshData {
+        MeshData {
+            positions: vec![
+                Float3::new(0.0, 1.0, -1.0),
+                Float3::new(0.0, 1.0, 1.0),
+                Float3::new(-1.0, 0.0, 0.0),
+                Float3::new(1.0, 0.0, 0.0),
+            ],
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: vec![[0, 2, 1], [0, 1, 3]],
+        }
+    }
+
+    #[test]
+    fn compute_normals_averages_adjacent_faces() {
+        let mut mesh = roof();
+        mesh.compute_normals();
+        let s = 0.5f64.sqrt();
+        let expected = [
+            Float3::yaxis(),
+            Float3::yaxis(),
+            Float3::new(-s, s, 0.0),
+            Float3::new(s, s, 0.0),
+        ];
+        for (n, e) in mesh.normals.iter().zip(expected) {
+            assert!((*n - e).length() < 1e-9, "{n:?} != {e:?}");
+        }
+    }
+
+    #[test]
+    fn mesh_interpolates_vertex_normals() {
+        let mut mesh = roof();
+        mesh.compute_normals();
+        let mesh = TriangleMesh::new(mesh, Arc::new(Lambertian::new(Float3::full(0.5))));
+        //稜線の上では両側の面の平均、その外側では稜線と反対の頂点の法線との補間になる
+        let s = 0.5f64.sqrt();
+        for (x, expected) in [
+            (0.0, Float3::yaxis()),
+            (-0.5, Float3::new(-s, 1.0 + s, 0.0)),
+            (0.5, Float3::new(s, 1.0 + s, 0.0)),
+        ] {
+            let ray = Ray::new(Float3::new(x, 5.0, 0.0), -Float3::yaxis());
+            let hit = mesh.hit(&ray, 0.001, f64::MAX).unwrap();
+            let expected = expected.normalize();
+            assert!(
+                (hit.n - expected).length() < 1e-9,
+                "{:?} != {expected:?}",
+                hit.n
+            );
+        }
+    }
+}
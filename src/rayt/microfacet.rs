@@ -0,0 +1,112 @@
+use rand::Rng;
+
+use super::float3::{Float3, Vector3};
+use super::math::{PI, PI2};
+
+/// 法線nをz軸とする局所座標系
+/// マイクロファセットの式は局所座標系で書くと簡単になる
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    s: Vector3,
+    t: Vector3,
+    n: Vector3,
+}
+
+impl Frame {
+    pub fn new(n: Vector3) -> Self {
+        let (s, t) = n.orthonormal_basis();
+        Self { s, t, n }
+    }
+
+    pub fn to_local(self, v: Vector3) -> Vector3 {
+        Float3::new(v.dot(self.s), v.dot(self.t), v.dot(self.n))
+    }
+
+    pub fn to_world(self, v: Vector3) -> Vector3 {
+        self.s * v.x() + self.t * v.y() + self.n * v.z()
+    }
+}
+
+/// GGX(Trowbridge-Reitz)のマイクロファセット分布(等方性)
+/// 方向はいずれも局所座標系で、巨視的な法線を+zとする
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx {
+    alpha: f64,
+}
+
+impl Ggx {
+    /// 見た目の粗さroughnessから作る。α = roughness^2とすると粗さの変化が見た目に比例する
+    pub fn from_roughness(roughness: f64) -> Self {
+        let roughness = roughness.clamp(0.0, 1.0);
+        Self {
+            alpha: roughness * roughness,
+        }
+    }
+
+    /// 十分に滑らかで、完全な鏡面(デルタ関数)として扱うべきかどうか
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < 1e-3
+    }
+
+    /// マイクロファセットの法線の分布D(h)
+    pub fn d(&self, h: Vector3) -> f64 {
+        if h.z() <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let denom = h.z() * h.z() * (a2 - 1.0) + 1.0;
+        a2 / (PI * denom * denom)
+    }
+
+    /// SmithのマスキングのΛ関数
+    fn lambda(&self, v: Vector3) -> f64 {
+        let cos2 = v.z() * v.z();
+        if cos2 <= 0.0 {
+            return f64::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        0.5 * ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0)
+    }
+
+    /// vの方向から見たときに隠れていないマイクロファセットの割合
+    pub fn g1(&self, v: Vector3) -> f64 {
+        1.0 / (1.0 + self.lambda(v))
+    }
+
+    /// woとwiの両方から見えている割合(高さに相関のあるSmithのマスキング・シャドウイング関数)
+    pub fn g2(&self, wo: Vector3, wi: Vector3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// woから見えるマイクロファセットの法線の分布 D_wo(h) = G1(wo) max(0, wo・h) D(h) / wo.z
+    pub fn pdf_visible(&self, wo: Vector3, h: Vector3) -> f64 {
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(h).max(0.0) * self.d(h) / wo.z()
+    }
+
+    /// D_wo(h)に従ってマイクロファセットの法線を選ぶ
+    /// Heitz "Sampling the GGX Distribution of Visible Normals" (2018)
+    pub fn sample_visible(&self, wo: Vector3) -> Vector3 {
+        let mut rng = rand::thread_rng();
+        //αで引き伸ばして半球に直す
+        let vh = Float3::new(self.alpha * wo.x(), self.alpha * wo.y(), wo.z()).normalize();
+        let len2 = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if len2 > 0.0 {
+            Float3::new(-vh.y(), vh.x(), 0.0) / len2.sqrt()
+        } else {
+            Float3::xaxis()
+        };
+        let t2 = vh.cross(t1);
+        //vhに垂直な円板の点を選び、vhから見える半円に合うように縮める
+        let r = rng.gen::<f64>().sqrt();
+        let phi = PI2 * rng.gen::<f64>();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        //引き伸ばしを戻す
+        Float3::new(self.alpha * nh.x(), self.alpha * nh.y(), nh.z().max(0.0)).normalize()
+    }
+}
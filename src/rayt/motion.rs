@@ -0,0 +1,36 @@
+use super::float3::Point3;
+
+/// 時刻ごとの位置を並べたキーフレーム。間の時刻は線形に補間する
+/// 最初のキーより前と最後のキーより後は、それぞれ端の位置で止まっているものとする
+#[derive(Debug, Clone)]
+pub struct Keyframes {
+    keys: Vec<(f64, Point3)>,
+}
+
+impl Keyframes {
+    /// keysは(時刻, 位置)の組。時刻の順に並べ替える
+    pub fn new(mut keys: Vec<(f64, Point3)>) -> Self {
+        assert!(!keys.is_empty(), "keyframes must not be empty");
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { keys }
+    }
+
+    pub fn at(&self, time: f64) -> Point3 {
+        //timeより後にある最初のキー
+        let i = self.keys.partition_point(|&(t, _)| t <= time);
+        if i == 0 {
+            return self.keys[0].1;
+        }
+        if i == self.keys.len() {
+            return self.keys[i - 1].1;
+        }
+        let (t0, p0) = self.keys[i - 1];
+        let (t1, p1) = self.keys[i];
+        p0.lerp(p1, (time - t0) / (t1 - t0))
+    }
+
+    /// 全てのキーの位置。キーの間は線形に動くので、これらを囲めば動き全体を囲める
+    pub fn points(&self) -> impl Iterator<Item = Point3> + '_ {
+        self.keys.iter().map(|&(_, p)| p)
+    }
+}
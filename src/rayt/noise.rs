@@ -0,0 +1,159 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use super::float3::{Float3, Point3};
+
+/// Perlinのグラディエントノイズ(Improved Noise, 2002)
+/// 同じseedからは常に同じノイズになるので、レンダリング結果を再現できる
+#[derive(Debug, Clone)]
+pub struct Perlin {
+    /// 0から255の並べ替えを2回繰り返したもの。格子点のハッシュに使う
+    perm: Vec<u8>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut perm: Vec<u8> = (0..=255).collect();
+        perm.shuffle(&mut rng);
+        perm.extend_from_within(..);
+        Self { perm }
+    }
+
+    fn hash(&self, x: usize, y: usize, z: usize) -> u8 {
+        let perm = &self.perm;
+        perm[perm[perm[x] as usize + y] as usize + z]
+    }
+
+    /// pでのノイズの値。おおよそ-1から1の範囲で、整数の格子点では0になる
+    pub fn noise(&self, p: Point3) -> f64 {
+        let floor = p.0.map(f64::floor);
+        //格子の位置は256で繰り返す
+        let cell = floor.map(|f| (f as i64).rem_euclid(256) as usize);
+        let [x, y, z] = [p.x() - floor[0], p.y() - floor[1], p.z() - floor[2]];
+        let [u, v, w] = [fade(x), fade(y), fade(z)];
+        let [i, j, k] = cell;
+
+        let corner = |di: usize, dj: usize, dk: usize| {
+            let h = self.hash(i + di, j + dj, k + dk);
+            gradient(h, x - di as f64, y - dj as f64, z - dk as f64)
+        };
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+                lerp(u, corner(0, 1, 0), corner(1, 1, 0)),
+            ),
+            lerp(
+                v,
+                lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+                lerp(u, corner(0, 1, 1), corner(1, 1, 1)),
+            ),
+        )
+    }
+
+    /// fBm(fractional Brownian motion)。周波数を2倍、振幅を半分にしながらoctaves回重ねる
+    /// 振幅の合計で割るので、おおよそ-1から1の範囲になる
+    pub fn fbm(&self, p: Point3, octaves: u32) -> f64 {
+        let (sum, total) = self.octaves(p, octaves, |n| n);
+        sum / total
+    }
+
+    /// 乱流。ノイズの絶対値をfBmと同じように重ねる。0から1の範囲になる
+    pub fn turbulence(&self, p: Point3, octaves: u32) -> f64 {
+        let (sum, total) = self.octaves(p, octaves, f64::abs);
+        sum / total
+    }
+
+    /// 各オクターブのノイズをfで変換して重ねた合計と、振幅の合計
+    fn octaves(&self, p: Point3, octaves: u32, f: impl Fn(f64) -> f64) -> (f64, f64) {
+        let mut sum = 0.0;
+        let mut total = 0.0;
+        let mut amplitude = 1.0;
+        let mut p = p;
+        for _ in 0..octaves.max(1) {
+            sum += amplitude * f(self.noise(p));
+            total += amplitude;
+            amplitude *= 0.5;
+            p = p * 2.0;
+        }
+        (sum, total)
+    }
+}
+
+/// 格子点の間の補間に使う曲線 6t^5 - 15t^4 + 10t^3。格子点で1階と2階の微分が0になる
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+/// ハッシュで立方体の辺の中点への12方向から勾配を選び、(x, y, z)との内積を返す
+fn gradient(hash: u8, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+/// Worleyのセルラーノイズ
+/// 空間を単位立方体に区切って各セルに1つずつ特徴点を置き、最も近い特徴点までの距離を値とする
+#[derive(Debug, Clone)]
+pub struct Worley {
+    seed: u64,
+}
+
+impl Worley {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    /// セル(i, j, k)の特徴点
+    fn feature_point(&self, i: i64, j: i64, k: i64) -> Point3 {
+        let mut h = self.seed;
+        for c in [i, j, k] {
+            h = splitmix64(h ^ c as u64);
+        }
+        //64bitのハッシュを21bitずつに分けて、セルの中の位置にする
+        let unit = |bits: u64| (bits & 0x1f_ffff) as f64 / (1u64 << 21) as f64;
+        Float3::new(
+            i as f64 + unit(h),
+            j as f64 + unit(h >> 21),
+            k as f64 + unit(h >> 42),
+        )
+    }
+
+    /// pから最も近い特徴点までの距離。ほとんどの点で0から1の範囲になる
+    pub fn distance(&self, p: Point3) -> f64 {
+        let cell = p.0.map(|c| c.floor() as i64);
+        let mut nearest = f64::MAX;
+        //特徴点はセルの中にあるので、隣り合う27個のセルを調べれば十分
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let q = self.feature_point(cell[0] + di, cell[1] + dj, cell[2] + dk);
+                    nearest = nearest.min((q - p).length_squared());
+                }
+            }
+        }
+        nearest.sqrt()
+    }
+}
+
+/// 64bitの整数のハッシュ(SplitMix64)
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
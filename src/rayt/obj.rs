@@ -0,0 +1,638 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use log::warn;
+
+use super::float3::{Color, Float3, Point3, Vector3};
+use super::mesh::{MeshData, TriangleMesh};
+use super::render::{DiffuseLight, Material, Principled};
+use super::shape::ShapeList;
+use super::texture::{SolidColor, Texture};
+
+/// OBJ/MTLファイルの読み込みエラー
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Parse { .. } => None,
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|source| ObjError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// 1行分のパース結果。エラーの場合はメッセージだけを返し、呼び出し側でファイル名と行番号を付ける
+type LineResult<T> = Result<T, String>;
+
+fn parse_f64(token: Option<&str>, what: &str) -> LineResult<f64> {
+    let token = token.ok_or_else(|| format!("missing {}", what))?;
+    token
+        .parse::<f64>()
+        .map_err(|_| format!("invalid {} `{}`", what, token))
+}
+
+fn parse_float3<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> LineResult<Float3> {
+    Ok(Float3::new(
+        parse_f64(tokens.next(), "x")?,
+        parse_f64(tokens.next(), "y")?,
+        parse_f64(tokens.next(), "z")?,
+    ))
+}
+
+/// MTLファイルのマテリアル定義
+/// Pr、Pm、Ps、Pc、PcrはPBRの拡張で、書かれていなければNone
+#[derive(Debug, Clone)]
+struct MtlMaterial {
+    kd: Color,
+    ks: Color,
+    ke: Color,
+    ns: f64,
+    d: f64,
+    ni: f64,
+    roughness: Option<f64>,
+    metallic: Option<f64>,
+    sheen: Option<f64>,
+    clearcoat: Option<f64>,
+    clearcoat_roughness: Option<f64>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            kd: Float3::full(0.8),
+            ks: Float3::zero(),
+            ke: Float3::zero(),
+            ns: 0.0,
+            d: 1.0,
+            ni: 1.0,
+            roughness: None,
+            metallic: None,
+            sheen: None,
+            clearcoat: None,
+            clearcoat_roughness: None,
+        }
+    }
+}
+
+/// MTLのマテリアルから求めたPrincipledのパラメータ
+#[derive(Debug, Clone, PartialEq)]
+struct PrincipledParams {
+    base_color: Color,
+    metallic: f64,
+    roughness: f64,
+    transmission: f64,
+    ior: f64,
+    sheen: Option<f64>,
+    clearcoat: Option<f64>,
+    clearcoat_roughness: Option<f64>,
+}
+
+fn luminance(c: Color) -> f64 {
+    c.x() * 0.2126 + c.y() * 0.7152 + c.z() * 0.0722
+}
+
+impl MtlMaterial {
+    /// Principledのマテリアルに対応付ける。Keが0でなければ光源にする
+    fn to_material(&self) -> Arc<dyn Material> {
+        if luminance(self.ke) > 0.0 {
+            return Arc::new(DiffuseLight::new(self.ke));
+        }
+        let params = self.principled();
+        let constant =
+            |value: f64| -> Arc<dyn Texture> { Arc::new(SolidColor::new(Float3::full(value))) };
+        let mut material = Principled::new(params.base_color)
+            .with_metallic(constant(params.metallic))
+            .with_roughness(constant(params.roughness))
+            .with_transmission(constant(params.transmission))
+            .with_ior(params.ior);
+        if let Some(sheen) = params.sheen {
+            material = material.with_sheen(constant(sheen));
+        }
+        if let Some(clearcoat) = params.clearcoat {
+            material = material.with_clearcoat(constant(clearcoat));
+        }
+        if let Some(roughness) = params.clearcoat_roughness {
+            material = material.with_clearcoat_roughness(constant(roughness));
+        }
+        Arc::new(material)
+    }
+
+    /// PBRの拡張が書かれていなければ従来の値から推定する
+    /// Pmがなければ、KsがKdより明るい場合に金属(色はKs)とし、PrがなければNsから粗さを求める
+    /// dが1未満なら1 - dの割合で透過する(屈折率はNi)
+    fn principled(&self) -> PrincipledParams {
+        let transmission = (1.0 - self.d).clamp(0.0, 1.0);
+        let looks_metal = transmission == 0.0 && luminance(self.ks) > luminance(self.kd);
+        let metallic = self.metallic.unwrap_or(if looks_metal { 1.0 } else { 0.0 });
+        let base_color = if self.metallic.is_none() && looks_metal {
+            self.ks
+        } else {
+            self.kd
+        };
+        //Phongの指数をGGXのα = sqrt(2 / (Ns + 2))に対応させ、粗さ(αの平方根)にする
+        //Ns=0で1、大きくなるほど0に近づく
+        let roughness = self
+            .roughness
+            .unwrap_or_else(|| (2.0 / (self.ns.max(0.0) + 2.0)).sqrt().sqrt());
+        //Niが書かれていない(1.0のまま)場合は一般的なガラスの値を使う
+        let ior = if self.ni > 1.0 { self.ni } else { 1.5 };
+        PrincipledParams {
+            base_color,
+            metallic,
+            roughness,
+            transmission,
+            ior,
+            sheen: self.sheen,
+            clearcoat: self.clearcoat,
+            clearcoat_roughness: self.clearcoat_roughness,
+        }
+    }
+}
+
+/// MTLファイルの内容sourceを読み込む。pathはエラーメッセージに使う
+fn parse_mtl(source: &str, path: &Path) -> Result<HashMap<String, MtlMaterial>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (index, line) in source.lines().enumerate() {
+        let parse_error = |message: String| ObjError::Parse {
+            path: path.to_path_buf(),
+            line: index + 1,
+            message,
+        };
+        let line = line.split('#').next().unwrap().trim();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        if keyword == "newmtl" {
+            let name = tokens.collect::<Vec<_>>().join(" ");
+            if name.is_empty() {
+                return Err(parse_error("newmtl without a name".to_string()));
+            }
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material);
+            }
+            current = Some((name, MtlMaterial::default()));
+            continue;
+        }
+        let Some((_, material)) = current.as_mut() else {
+            return Err(parse_error(format!("`{}` before newmtl", keyword)));
+        };
+        let result: LineResult<()> = (|| {
+            match keyword {
+                "Kd" => material.kd = parse_float3(&mut tokens)?,
+                "Ks" => material.ks = parse_float3(&mut tokens)?,
+                "Ke" => material.ke = parse_float3(&mut tokens)?,
+                "Ns" => material.ns = parse_f64(tokens.next(), "Ns")?,
+                "d" => material.d = parse_f64(tokens.next(), "d")?,
+                "Tr" => material.d = 1.0 - parse_f64(tokens.next(), "Tr")?,
+                "Ni" => material.ni = parse_f64(tokens.next(), "Ni")?,
+                "Pr" => material.roughness = Some(parse_f64(tokens.next(), "Pr")?),
+                "Pm" => material.metallic = Some(parse_f64(tokens.next(), "Pm")?),
+                "Ps" => material.sheen = Some(parse_f64(tokens.next(), "Ps")?),
+                "Pc" => material.clearcoat = Some(parse_f64(tokens.next(), "Pc")?),
+                "Pcr" => material.clearcoat_roughness = Some(parse_f64(tokens.next(), "Pcr")?),
+                _ => {}
+            }
+            Ok(())
+        })();
+        result.map_err(parse_error)?;
+    }
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+    Ok(materials)
+}
+
+/// 面の頂点。それぞれ位置・UV・法線のインデックス(0始まり)
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+/// 同じグループ・同じマテリアルの面をまとめたもの
+struct MeshBuilder {
+    material: Option<String>,
+    faces: Vec<[FaceVertex; 3]>,
+}
+
+/// OBJのインデックス(1始まり、負の値は末尾からの相対位置)を0始まりに変換する
+fn resolve_index(token: &str, count: usize, what: &str) -> LineResult<usize> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| format!("invalid {} index `{}`", what, token))?;
+    let resolved = if index > 0 {
+        index - 1
+    } else if index < 0 {
+        count as i64 + index
+    } else {
+        return Err(format!("{} index must not be 0", what));
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(format!(
+            "{} index {} out of range ({} defined)",
+            what, index, count
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+/// Wavefront OBJファイルを読み込む
+/// グループとマテリアルの組み合わせごとにTriangleMeshを作成し、ShapeListにまとめて返す
+/// 多角形の面は扇形に三角形分割する
+/// materialを指定した場合はMTLのマテリアルの代わりに全ての面で使う
+pub fn load_obj(path: &Path, material: Option<Arc<dyn Material>>) -> Result<ShapeList, ObjError> {
+    let source = read_file(path)?;
+    Ok(parse_obj(&source, path, read_file)?.into_shapes(path, material))
+}
+
+/// OBJファイルを読み込んだ結果。面はまだ形状にしていない
+struct ObjData {
+    positions: Vec<Point3>,
+    normals: Vec<Vector3>,
+    uvs: Vec<[f64; 2]>,
+    mtl: HashMap<String, MtlMaterial>,
+    builders: Vec<MeshBuilder>,
+}
+
+/// OBJファイルの内容sourceを読み込む。pathはエラーメッセージとmtllibの相対パスの基準に使う
+/// mtllibのファイルはreadで読む
+fn parse_obj(
+    source: &str,
+    path: &Path,
+    read: impl Fn(&Path) -> Result<String, ObjError>,
+) -> Result<ObjData, ObjError> {
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut normals: Vec<Vector3> = Vec::new();
+    let mut uvs: Vec<[f64; 2]> = Vec::new();
+    let mut mtl: HashMap<String, MtlMaterial> = HashMap::new();
+    let mut builders: Vec<MeshBuilder> = vec![MeshBuilder {
+        material: None,
+        faces: Vec::new(),
+    }];
+
+    for (index, line) in source.lines().enumerate() {
+        let parse_error = |message: String| ObjError::Parse {
+            path: path.to_path_buf(),
+            line: index + 1,
+            message,
+        };
+        let line = line.split('#').next().unwrap().trim();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        match keyword {
+            "v" => positions.push(parse_float3(&mut tokens).map_err(parse_error)?),
+            "vn" => normals.push(parse_float3(&mut tokens).map_err(parse_error)?),
+            "vt" => {
+                let u = parse_f64(tokens.next(), "u").map_err(parse_error)?;
+                let v = match tokens.next() {
+                    Some(token) => parse_f64(Some(token), "v").map_err(parse_error)?,
+                    None => 0.0,
+                };
+                uvs.push([u, v]);
+            }
+            "f" => {
+                let vertices = tokens
+                    .map(|token| {
+                        let mut parts = token.split('/');
+                        let v = resolve_index(parts.next().unwrap(), positions.len(), "vertex")?;
+                        let vt = match parts.next() {
+                            Some("") | None => None,
+                            Some(t) => Some(resolve_index(t, uvs.len(), "texture coordinate")?),
+                        };
+                        let vn = match parts.next() {
+                            Some("") | None => None,
+                            Some(n) => Some(resolve_index(n, normals.len(), "normal")?),
+                        };
+                        if parts.next().is_some() {
+                            return Err(format!("invalid face vertex `{}`", token));
+                        }
+                        Ok((v, vt, vn))
+                    })
+                    .collect::<LineResult<Vec<FaceVertex>>>()
+                    .map_err(parse_error)?;
+                if vertices.len() < 3 {
+                    return Err(parse_error(format!(
+                        "face needs at least 3 vertices, got {}",
+                        vertices.len()
+                    )));
+                }
+                let faces = &mut builders.last_mut().unwrap().faces;
+                for i in 1..vertices.len() - 1 {
+                    faces.push([vertices[0], vertices[i], vertices[i + 1]]);
+                }
+            }
+            "g" | "o" => {
+                let material = builders.last().unwrap().material.clone();
+                builders.push(MeshBuilder {
+                    material,
+                    faces: Vec::new(),
+                });
+            }
+            "usemtl" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                if !mtl.contains_key(&name) {
+                    return Err(parse_error(format!("unknown material `{}`", name)));
+                }
+                builders.push(MeshBuilder {
+                    material: Some(name),
+                    faces: Vec::new(),
+                });
+            }
+            "mtllib" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                if name.is_empty() {
+                    return Err(parse_error("mtllib without a file name".to_string()));
+                }
+                let mtl_path = base_dir.join(name);
+                mtl.extend(parse_mtl(&read(&mtl_path)?, &mtl_path)?);
+            }
+            // スムージンググループ、線、点は使わない
+            "s" | "l" | "p" => {}
+            _ => warn!(
+                "{}:{}: ignoring unsupported statement `{}`",
+                path.display(),
+                index + 1,
+                keyword
+            ),
+        }
+    }
+
+    Ok(ObjData {
+        positions,
+        normals,
+        uvs,
+        mtl,
+        builders,
+    })
+}
+
+impl ObjData {
+    /// 面のあるグループごとにTriangleMeshを作る。pathは警告に表示する
+    fn into_shapes(self, path: &Path, material: Option<Arc<dyn Material>>) -> ShapeList {
+        let default_material: Arc<dyn Material> = Arc::new(Principled::new(Float3::full(0.73)));
+        let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+        let mut shapes = ShapeList::new();
+        for builder in self.builders.iter().filter(|b| !b.faces.is_empty()) {
+            let material = match (&material, &builder.material) {
+                (Some(material), _) => Arc::clone(material),
+                (None, Some(name)) => Arc::clone(
+                    materials
+                        .entry(name.clone())
+                        .or_insert_with(|| self.mtl[name].to_material()),
+                ),
+                (None, None) => Arc::clone(&default_material),
+            };
+            let mesh = build_mesh(
+                path,
+                &builder.faces,
+                &self.positions,
+                &self.normals,
+                &self.uvs,
+            );
+            shapes.push(Box::new(TriangleMesh::new(mesh, material)));
+        }
+        shapes
+    }
+}
+
+/// OBJは位置・UV・法線を別々のインデックスで参照するので、組み合わせごとに1つの頂点にまとめる
+/// 法線やUVを持たない頂点が1つでもあれば、そのメッシュでは使わない
+/// 長さが0の法線は正規化できないので、そのような法線がある場合もファイルの法線は使わない
+/// ファイルの法線を使わない場合は、位置を共有する面の法線を平均してスムーズシェーディングにする
+fn build_mesh(
+    path: &Path,
+    faces: &[[FaceVertex; 3]],
+    positions: &[Point3],
+    normals: &[Vector3],
+    uvs: &[[f64; 2]],
+) -> MeshData {
+    let mut use_normals = faces.iter().flatten().all(|(_, _, vn)| vn.is_some());
+    if use_normals
+        && faces.iter().flatten().any(|(_, _, vn)| {
+            let n = normals[vn.unwrap()];
+            !(n.length_squared() > 0.0 && n.length_squared().is_finite())
+        })
+    {
+        warn!(
+            "{}: ignoring vertex normals of a mesh with zero-length normals",
+            path.display()
+        );
+        use_normals = false;
+    }
+    let use_uvs = faces.iter().flatten().all(|(_, vt, _)| vt.is_some());
+    //UVの継ぎ目で頂点が分かれても法線が揃うように、OBJの位置ごとに法線を求める
+    let smooth_normals = (!use_normals).then(|| {
+        let mut smooth = MeshData {
+            positions: positions.to_vec(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: faces.iter().map(|face| face.map(|(v, _, _)| v)).collect(),
+        };
+        smooth.compute_normals();
+        smooth.normals
+    });
+    let mut mesh = MeshData {
+        positions: Vec::new(),
+        normals: Vec::new(),
+        uvs: Vec::new(),
+        indices: Vec::with_capacity(faces.len()),
+    };
+    let mut vertex_map: HashMap<FaceVertex, usize> = HashMap::new();
+    for face in faces {
+        let indices = face.map(|(v, vt, vn)| {
+            let key = (v, vt.filter(|_| use_uvs), vn.filter(|_| use_normals));
+            *vertex_map.entry(key).or_insert_with(|| {
+                mesh.positions.push(positions[v]);
+                match (key.2, &smooth_normals) {
+                    (Some(vn), _) => mesh.normals.push(normals[vn].normalize()),
+                    (None, Some(smooth)) => mesh.normals.push(smooth[v]),
+                    (None, None) => {}
+                }
+                if let Some(vt) = key.1 {
+                    mesh.uvs.push(uvs[vt]);
+                }
+                mesh.positions.len() - 1
+            })
+        });
+        mesh.indices.push(indices);
+    }
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MTL: &str = "\
+newmtl lamp
+Ke 4 4 4
+
+newmtl gold
+Kd 0.1 0.1 0.1
+Ks 1.0 0.8 0.3
+Ns 30
+
+newmtl glass
+Kd 0.9 0.9 0.9
+d 0.4
+Ni 1.33
+
+newmtl plastic
+Kd 0.8 0.2 0.2
+Ks 0.1 0.1 0.1
+";
+
+    /// mtllibはファイルから読まずにMTLの内容を返す
+    fn parse(source: &str) -> Result<ObjData, ObjError> {
+        parse_obj(source, Path::new("test.obj"), |_| Ok(MTL.to_string()))
+    }
+
+    fn parse_message(source: &str) -> (usize, String) {
+        match parse(source) {
+            Err(ObjError::Parse { line, message, .. }) => (line, message),
+            Err(error) => panic!("unexpected error: {error}"),
+            Ok(_) => panic!("expected a parse error"),
+        }
+    }
+
+    fn vertices(data: &ObjData) -> Vec<[usize; 3]> {
+        data.builders
+            .iter()
+            .flat_map(|b| &b.faces)
+            .map(|face| face.map(|(v, _, _)| v))
+            .collect()
+    }
+
+    #[test]
+    fn negative_indices_count_from_the_end() {
+        let data = parse(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvn 0 0 1\n\
+             f -3/-2/-1 -2/-1/-1 -1/-1/-1\n",
+        )
+        .unwrap();
+        assert_eq!(
+            data.builders[0].faces,
+            vec![[
+                (0, Some(0), Some(0)),
+                (1, Some(1), Some(0)),
+                (2, Some(1), Some(0))
+            ]]
+        );
+    }
+
+    #[test]
+    fn zero_and_out_of_range_indices_are_rejected() {
+        let (line, message) = parse_message("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 0 1 2\n");
+        assert_eq!((line, message.as_str()), (4, "vertex index must not be 0"));
+        let (line, message) = parse_message("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n");
+        assert_eq!(
+            (line, message.as_str()),
+            (4, "vertex index 4 out of range (3 defined)")
+        );
+        let (line, message) = parse_message("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 -4\n");
+        assert_eq!(
+            (line, message.as_str()),
+            (4, "vertex index -4 out of range (3 defined)")
+        );
+        let (_, message) = parse_message("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/1 2/1 3/1\n");
+        assert_eq!(
+            message,
+            "texture coordinate index 1 out of range (0 defined)"
+        );
+    }
+
+    #[test]
+    fn polygons_are_fan_triangulated() {
+        let data = parse(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv -1 1 0\n\
+             f 1 2 3 4\nf 1 2 3 4 5\n",
+        )
+        .unwrap();
+        assert_eq!(
+            vertices(&data),
+            vec![[0, 1, 2], [0, 2, 3], [0, 1, 2], [0, 2, 3], [0, 3, 4]]
+        );
+        let (_, message) = parse_message("v 0 0 0\nv 1 0 0\nf 1 2\n");
+        assert_eq!(message, "face needs at least 3 vertices, got 2");
+    }
+
+    #[test]
+    fn unknown_material_is_rejected() {
+        let (line, message) = parse_message("mtllib scene.mtl\nusemtl gold\nusemtl silver\n");
+        assert_eq!((line, message.as_str()), (3, "unknown material `silver`"));
+        //mtllibがなければどの名前も未定義になる
+        let data = parse("mtllib scene.mtl\nusemtl gold\n").unwrap();
+        assert_eq!(
+            data.builders.last().unwrap().material.as_deref(),
+            Some("gold")
+        );
+        assert!(matches!(
+            parse_obj("usemtl gold\n", Path::new("test.obj"), read_file),
+            Err(ObjError::Parse { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn mtl_maps_to_principled_and_lights() {
+        let mtl = parse_mtl(MTL, Path::new("test.mtl")).unwrap();
+        assert!(mtl["lamp"].to_material().is_emissive());
+        assert!(!mtl["gold"].to_material().is_emissive());
+
+        //KsがKdより明るいので、Ksを色とする金属になる。Ns=30は粗さ0.5
+        let gold = mtl["gold"].principled();
+        assert_eq!(gold.base_color, Float3::new(1.0, 0.8, 0.3));
+        assert_eq!(gold.metallic, 1.0);
+        assert!((gold.roughness - 0.5).abs() < 1e-12);
+        assert_eq!(gold.transmission, 0.0);
+
+        let glass = mtl["glass"].principled();
+        assert_eq!(glass.base_color, Float3::full(0.9));
+        assert_eq!(glass.metallic, 0.0);
+        assert!((glass.transmission - 0.6).abs() < 1e-12);
+        assert_eq!(glass.ior, 1.33);
+
+        //Ns, Niが無ければ粗さ1、屈折率1.5
+        let plastic = mtl["plastic"].principled();
+        assert_eq!(plastic.base_color, Float3::new(0.8, 0.2, 0.2));
+        assert_eq!(plastic.metallic, 0.0);
+        assert_eq!(plastic.roughness, 1.0);
+        assert_eq!(plastic.ior, 1.5);
+    }
+}
@@ -1,9 +1,427 @@
-use super::float3::Vector3;
+use std::ops::{Mul, Neg};
 
+use super::float3::{Float3, Vector3};
+
+/// 四元数。0がベクトル部(x, y, z)、1がスカラー部w
+/// 回転には単位四元数を使う。軸axis周りの角度θの回転は(axis * sin(θ/2), cos(θ/2))
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Quaternion(pub Vector3, pub f64);
 
+/// 3x3の行列。m[行][列]
+pub type Matrix3 = [[f64; 3]; 3];
+
 impl Quaternion {
+    #[allow(dead_code, reason = "成分を直接指定する構築子。今はテストだけが使う")]
     pub const fn new(x: f64, y: f64, z: f64, w: f64) -> Self {
         Self(Vector3::new(x, y, z), w)
     }
+
+    /// 回転しない四元数
+    #[allow(dead_code, reason = "回転の合成の初期値。今はテストだけが使う")]
+    pub const fn identity() -> Self {
+        Self::new(0.0, 0.0, 0.0, 1.0)
+    }
+
+    /// axis周りにangle(ラジアン)回転する四元数。axisは正規化しなくてよい
+    pub fn from_axis_angle(axis: Vector3, angle: f64) -> Self {
+        let (sin, cos) = (angle * 0.5).sin_cos();
+        Self(axis.normalize() * sin, cos)
+    }
+
+    /// オイラー角(ラジアン)から作成する。x軸、y軸、z軸の順に回転する
+    pub fn from_euler(x: f64, y: f64, z: f64) -> Self {
+        Self::from_axis_angle(Float3::zaxis(), z)
+            * Self::from_axis_angle(Float3::yaxis(), y)
+            * Self::from_axis_angle(Float3::xaxis(), x)
+    }
+
+    /// 回転行列から作成する。行列は正規直交で行列式が1であること
+    /// Shepperdの方法で、対角成分の大きいものから求めて誤差を抑える
+    #[allow(
+        dead_code,
+        reason = "to_matrixの逆変換。行列で与えられた回転を取り込むときに使う"
+    )]
+    pub fn from_matrix(m: &Matrix3) -> Self {
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        let q = if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            Self::new(
+                (m[2][1] - m[1][2]) / s,
+                (m[0][2] - m[2][0]) / s,
+                (m[1][0] - m[0][1]) / s,
+                0.25 * s,
+            )
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.0;
+            Self::new(
+                0.25 * s,
+                (m[0][1] + m[1][0]) / s,
+                (m[0][2] + m[2][0]) / s,
+                (m[2][1] - m[1][2]) / s,
+            )
+        } else if m[1][1] > m[2][2] {
+            let s = (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.0;
+            Self::new(
+                (m[0][1] + m[1][0]) / s,
+                0.25 * s,
+                (m[1][2] + m[2][1]) / s,
+                (m[0][2] - m[2][0]) / s,
+            )
+        } else {
+            let s = (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.0;
+            Self::new(
+                (m[0][2] + m[2][0]) / s,
+                (m[1][2] + m[2][1]) / s,
+                0.25 * s,
+                (m[1][0] - m[0][1]) / s,
+            )
+        };
+        q.normalize()
+    }
+
+    pub fn x(&self) -> f64 {
+        self.0.x()
+    }
+    pub fn y(&self) -> f64 {
+        self.0.y()
+    }
+    pub fn z(&self) -> f64 {
+        self.0.z()
+    }
+    pub fn w(&self) -> f64 {
+        self.1
+    }
+
+    pub fn dot(&self, rhs: Self) -> f64 {
+        self.0.dot(rhs.0) + self.1 * rhs.1
+    }
+
+    pub fn length_squared(&self) -> f64 {
+        self.dot(*self)
+    }
+
+    pub fn length(&self) -> f64 {
+        self.length_squared().sqrt()
+    }
+
+    pub fn normalize(&self) -> Self {
+        let recip = self.length().recip();
+        Self(self.0 * recip, self.1 * recip)
+    }
+
+    /// 共役。単位四元数では逆の回転になる
+    #[allow(
+        dead_code,
+        reason = "単位四元数の逆回転をinverseより少ない計算で求める"
+    )]
+    pub fn conjugate(&self) -> Self {
+        Self(-self.0, self.1)
+    }
+
+    #[allow(dead_code, reason = "単位でない四元数も扱う逆元")]
+    pub fn inverse(&self) -> Self {
+        let recip = self.length_squared().recip();
+        Self(-self.0 * recip, self.1 * recip)
+    }
+
+    /// 回転軸と角度(ラジアン)。回転しない場合の軸はx軸とする
+    #[allow(dead_code, reason = "from_axis_angleの逆変換")]
+    pub fn to_axis_angle(self) -> (Vector3, f64) {
+        let q = self.normalize();
+        let sin = q.0.length();
+        if sin <= 0.0 {
+            return (Float3::xaxis(), 0.0);
+        }
+        (q.0 / sin, 2.0 * sin.atan2(q.1))
+    }
+
+    /// ベクトルを回転する。単位四元数であること
+    pub fn rotate(&self, v: Vector3) -> Vector3 {
+        //q * v * q^-1 を展開した式
+        let t = self.0.cross(v) * 2.0;
+        v + t * self.1 + self.0.cross(t)
+    }
+
+    /// 回転行列。単位四元数であること
+    pub fn to_matrix(self) -> Matrix3 {
+        let (x, y, z, w) = (self.x(), self.y(), self.z(), self.w());
+        [
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - z * w),
+                2.0 * (x * z + y * w),
+            ],
+            [
+                2.0 * (x * y + z * w),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - x * w),
+            ],
+            [
+                2.0 * (x * z - y * w),
+                2.0 * (y * z + x * w),
+                1.0 - 2.0 * (x * x + y * y),
+            ],
+        ]
+    }
+
+    /// 球面線形補間。t=0でself、t=1でotherになり、その間は一定の角速度で回転する
+    #[allow(dead_code, reason = "回転のキーフレームを補間するときに使う")]
+    pub fn slerp(&self, other: Self, t: f64) -> Self {
+        //qと-qは同じ回転なので、近い方の経路を通るように符号を揃える
+        let mut other = other;
+        let mut cos = self.dot(other);
+        if cos < 0.0 {
+            other = -other;
+            cos = -cos;
+        }
+        //角度が小さいときは線形補間で十分で、sinで割るときの誤差も避けられる
+        if cos > 0.9995 {
+            let q = Self(
+                self.0 + (other.0 - self.0) * t,
+                self.1 + (other.1 - self.1) * t,
+            );
+            return q.normalize();
+        }
+        let theta = cos.acos();
+        let sin = theta.sin();
+        let a = ((1.0 - t) * theta).sin() / sin;
+        let b = (t * theta).sin() / sin;
+        Self(self.0 * a + other.0 * b, self.1 * a + other.1 * b)
+    }
+}
+
+/// ハミルトン積。(a * b).rotate(v)はbで回転してからaで回転するのと同じ
+impl Mul for Quaternion {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self(
+            rhs.0 * self.1 + self.0 * rhs.1 + self.0.cross(rhs.0),
+            self.1 * rhs.1 - self.0.dot(rhs.0),
+        )
+    }
+}
+
+impl Neg for Quaternion {
+    type Output = Self;
+    fn neg(self) -> Self::Output {
+        Self(-self.0, -self.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::rayt::math::PI;
+
+    const EPS: f64 = 1e-9;
+    const CASES: usize = 1000;
+
+    fn random_vector(rng: &mut StdRng) -> Vector3 {
+        Float3::new(
+            rng.gen_range(-1.0..1.0),
+            rng.gen_range(-1.0..1.0),
+            rng.gen_range(-1.0..1.0),
+        ) * rng.gen_range(0.1..10.0)
+    }
+
+    /// 4次元の球の中から一様に選んで正規化した、ランダムな回転
+    fn random_rotation(rng: &mut StdRng) -> Quaternion {
+        loop {
+            let q = Quaternion::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+            let length_squared = q.length_squared();
+            if length_squared > 1e-4 && length_squared <= 1.0 {
+                return q.normalize();
+            }
+        }
+    }
+
+    fn assert_quaternion_eq(a: Quaternion, b: Quaternion, eps: f64) {
+        assert!(
+            (a.0 - b.0).length() <= eps && (a.1 - b.1).abs() <= eps,
+            "{a:?} != {b:?}"
+        );
+    }
+
+    /// qと-qは同じ回転を表すので、どちらかに一致すればよい
+    fn assert_same_rotation(a: Quaternion, b: Quaternion, eps: f64) {
+        let sign = if a.dot(b) < 0.0 { -1.0 } else { 1.0 };
+        assert_quaternion_eq(a, Quaternion(b.0 * sign, b.1 * sign), eps);
+    }
+
+    fn assert_vector_eq(a: Vector3, b: Vector3, eps: f64) {
+        assert!(
+            (a - b).length() <= eps * b.length().max(1.0),
+            "{a:?} != {b:?}"
+        );
+    }
+
+    fn mul_matrix(m: &Matrix3, v: Vector3) -> Vector3 {
+        Float3::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+
+    /// 2つの回転の間の角度
+    fn angle_between(a: Quaternion, b: Quaternion) -> f64 {
+        2.0 * a.dot(b).abs().min(1.0).acos()
+    }
+
+    #[test]
+    fn product_with_inverse_is_identity() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..CASES {
+            //単位四元数でなくても逆元になる
+            let q = random_rotation(&mut rng);
+            let q = Quaternion(q.0 * 3.0, q.1 * 3.0);
+            assert_quaternion_eq(q * q.inverse(), Quaternion::identity(), EPS);
+            assert_quaternion_eq(q.inverse() * q, Quaternion::identity(), EPS);
+            let unit = q.normalize();
+            assert_quaternion_eq(unit.conjugate(), unit.inverse(), EPS);
+        }
+    }
+
+    #[test]
+    fn product_composes_rotations() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..CASES {
+            let (a, b) = (random_rotation(&mut rng), random_rotation(&mut rng));
+            let v = random_vector(&mut rng);
+            assert_vector_eq((a * b).rotate(v), a.rotate(b.rotate(v)), EPS);
+        }
+    }
+
+    #[test]
+    fn rotate_matches_matrix_and_preserves_length() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..CASES {
+            let q = random_rotation(&mut rng);
+            let v = random_vector(&mut rng);
+            let rotated = q.rotate(v);
+            assert_vector_eq(rotated, mul_matrix(&q.to_matrix(), v), EPS);
+            assert!((rotated.length() - v.length()).abs() <= EPS * v.length());
+        }
+    }
+
+    #[test]
+    fn matrix_round_trip() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut cases: Vec<Quaternion> = (0..CASES).map(|_| random_rotation(&mut rng)).collect();
+        //対角成分の和が負になる、180度に近い回転も含める
+        for axis in [Float3::xaxis(), Float3::yaxis(), Float3::zaxis()] {
+            cases.push(Quaternion::from_axis_angle(axis, PI));
+            cases.push(Quaternion::from_axis_angle(axis, PI - 1e-3));
+        }
+        cases.push(Quaternion::identity());
+        for q in cases {
+            assert_same_rotation(Quaternion::from_matrix(&q.to_matrix()), q, 1e-9);
+        }
+    }
+
+    #[test]
+    fn axis_angle_rotates_as_expected() {
+        let quarter = PI * 0.5;
+        let (x, y, z) = (Float3::xaxis(), Float3::yaxis(), Float3::zaxis());
+        //右手系で、軸の正の方向から見て反時計回りに回転する
+        assert_vector_eq(Quaternion::from_axis_angle(z, quarter).rotate(x), y, EPS);
+        assert_vector_eq(Quaternion::from_axis_angle(x, quarter).rotate(y), z, EPS);
+        assert_vector_eq(Quaternion::from_axis_angle(y, quarter).rotate(z), x, EPS);
+        //軸は正規化しなくてよい
+        assert_vector_eq(
+            Quaternion::from_axis_angle(Float3::new(0.0, 0.0, 5.0), PI).rotate(x),
+            -x,
+            EPS,
+        );
+
+        let mut rng = StdRng::seed_from_u64(5);
+        for _ in 0..CASES {
+            let axis = random_vector(&mut rng).normalize();
+            let angle = rng.gen_range(1e-3..PI - 1e-3);
+            let q = Quaternion::from_axis_angle(axis, angle);
+            //軸上のベクトルは動かない
+            assert_vector_eq(q.rotate(axis * 2.0), axis * 2.0, EPS);
+            let (axis2, angle2) = q.to_axis_angle();
+            assert_vector_eq(axis2, axis, 1e-7);
+            assert!((angle2 - angle).abs() <= 1e-7);
+        }
+        assert_eq!(Quaternion::identity().to_axis_angle(), (x, 0.0));
+    }
+
+    #[test]
+    fn euler_applies_x_then_y_then_z() {
+        let quarter = PI * 0.5;
+        let (x, y, z) = (Float3::xaxis(), Float3::yaxis(), Float3::zaxis());
+        assert_same_rotation(
+            Quaternion::from_euler(quarter, 0.0, 0.0),
+            Quaternion::from_axis_angle(x, quarter),
+            EPS,
+        );
+        //yはx軸周りの回転でzに、さらにy軸周りの回転でxに移る
+        assert_vector_eq(
+            Quaternion::from_euler(quarter, quarter, 0.0).rotate(y),
+            x,
+            EPS,
+        );
+        //xはy軸周りの回転で-zに、さらにz軸周りの回転でも-zのまま
+        assert_vector_eq(
+            Quaternion::from_euler(0.0, quarter, quarter).rotate(x),
+            -z,
+            EPS,
+        );
+
+        let mut rng = StdRng::seed_from_u64(6);
+        for _ in 0..CASES {
+            let (a, b, c) = (
+                rng.gen_range(-PI..PI),
+                rng.gen_range(-PI..PI),
+                rng.gen_range(-PI..PI),
+            );
+            let v = random_vector(&mut rng);
+            let expected = Quaternion::from_axis_angle(z, c).rotate(
+                Quaternion::from_axis_angle(y, b)
+                    .rotate(Quaternion::from_axis_angle(x, a).rotate(v)),
+            );
+            assert_vector_eq(Quaternion::from_euler(a, b, c).rotate(v), expected, EPS);
+        }
+    }
+
+    #[test]
+    fn slerp_interpolates_at_constant_speed() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for case in 0..CASES {
+            let a = random_rotation(&mut rng);
+            //線形補間に切り替える、角度の小さい場合も含める
+            let b = if case % 4 == 0 {
+                a * Quaternion::from_axis_angle(random_vector(&mut rng), rng.gen_range(0.0..0.05))
+            } else {
+                random_rotation(&mut rng)
+            };
+            assert_quaternion_eq(a.slerp(b, 0.0), a, EPS);
+            assert_same_rotation(a.slerp(b, 1.0), b, EPS);
+
+            let total = angle_between(a, b);
+            let steps = 8;
+            let mut previous = a;
+            for i in 1..=steps {
+                let q = a.slerp(b, i as f64 / steps as f64);
+                assert!((q.length() - 1.0).abs() <= EPS);
+                //各区間で同じ角度だけ回転する。小さい角度での線形補間は近似なので少し許容する
+                let step = angle_between(previous, q);
+                assert!(
+                    (step - total / steps as f64).abs() <= 1e-6 + 1e-3 * total,
+                    "step {step} of total {total}"
+                );
+                previous = q;
+            }
+        }
+    }
 }
@@ -1,15 +1,42 @@
-use super::float3::{Float3, Point3, Vector3};
+use super::float3::{Point3, Vector3};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vector3,
+    /// レイが飛んだ時刻。動く形状はこの時刻の位置で交差判定する
+    pub time: f64,
+    /// レイを円錐とみなしたときの、原点での太さと距離1あたりの太さの広がり
+    /// テクスチャのミップマップのレベルを選ぶのに使う。どちらも0なら太さの無いレイになる
+    pub width: f64,
+    pub spread: f64,
 }
 
 impl Ray {
     pub fn new(origin: Point3, direction: Vector3) -> Self {
-        Self { origin, direction }
+        Self::with_time(origin, direction, 0.0)
+    }
+
+    pub fn with_time(origin: Point3, direction: Vector3, time: f64) -> Self {
+        Self {
+            origin,
+            direction,
+            time,
+            width: 0.0,
+            spread: 0.0,
+        }
+    }
+
+    pub fn with_cone(mut self, width: f64, spread: f64) -> Self {
+        self.width = width;
+        self.spread = spread;
+        self
+    }
+
+    /// パラメータtの位置でのレイの太さ。方向が正規化されていればtは原点からの距離になる
+    pub fn width_at(&self, t: f64) -> f64 {
+        self.width + self.spread * t
     }
 
     pub fn at(&self, t: f64) -> Point3 {
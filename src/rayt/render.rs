@@ -1,84 +1,1059 @@
-use super::float3::Float3;
+use std::sync::Arc;
+
+use super::float3::{Color, Float3, Vector3};
+use super::math::FRAC_1_PI;
+use super::microfacet::{Frame, Ggx};
 use super::ray::Ray;
 use super::shape::HitInfo;
+use super::texture::{SolidColor, Texture};
+
+use rand::prelude::*;
 
-pub struct ScatterInfo {
-    pub ray: Ray,
-    pub albedo: Float3,
+/// BSDFのサンプリング結果
+pub struct BsdfSample {
+    /// 光が来る方向(正規化済み、表面から外向き)
+    pub wi: Vector3,
+    /// wiを選んだ確率密度(立体角あたり)。鏡面の場合は意味を持たない
+    pub pdf: f64,
+    /// BSDF x cos / pdf。経路のスループットに掛ける
+    pub weight: Color,
+    /// デルタ関数の鏡面反射・屈折かどうか。trueなら光源を直接サンプリングしない
+    pub specular: bool,
 }
 
-impl ScatterInfo {
-    fn new(ray: Ray, albedo: Float3) -> Self {
-        Self { ray, albedo }
+impl BsdfSample {
+    fn new(wi: Vector3, pdf: f64, weight: Color) -> Self {
+        Self {
+            wi,
+            pdf,
+            weight,
+            specular: false,
+        }
+    }
+
+    fn specular(wi: Vector3, weight: Color) -> Self {
+        Self {
+            wi,
+            pdf: 0.0,
+            weight,
+            specular: true,
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Lambertian {
-    albedo: Float3,
+    albedo: Arc<dyn Texture>,
 }
 
+/// 金属の反射率の求め方
+#[derive(Debug, Clone)]
+enum MetalFresnel {
+    /// 垂直に入射したときの反射率(色)からSchlickの近似で求める
+    Schlick(Arc<dyn Texture>),
+    /// RGBごとの複素屈折率eta + ikからフレネルの式で求める
+    Complex { eta: Color, k: Color },
+}
+
+/// 金属。GGXのマイクロファセットモデルで、roughnessが0なら完全な鏡面反射になる
 #[derive(Debug, Clone)]
 pub struct Metal {
-    albedo: Float3,
-    fuzz: f64,
+    fresnel: MetalFresnel,
+    roughness: Arc<dyn Texture>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Float3) -> Self {
+        Self::with_texture(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn with_texture(albedo: Arc<dyn Texture>) -> Self {
         Self { albedo }
     }
 }
 
 impl Metal {
-    pub fn new(albedo: Float3, fuzz: f64) -> Self {
-        Self { albedo, fuzz }
+    /// albedoは垂直に入射したときの反射率
+    pub fn new(albedo: Float3, roughness: f64) -> Self {
+        Self::with_textures(
+            Arc::new(SolidColor::new(albedo)),
+            Arc::new(SolidColor::new(Float3::full(roughness))),
+        )
     }
+
+    /// roughnessはテクスチャの値をRGBの平均で数値にして使う
+    pub fn with_textures(albedo: Arc<dyn Texture>, roughness: Arc<dyn Texture>) -> Self {
+        Self {
+            fresnel: MetalFresnel::Schlick(albedo),
+            roughness,
+        }
+    }
+
+    /// RGBごとの複素屈折率eta + ikを持つ導体
+    pub fn conductor(eta: Color, k: Color, roughness: f64) -> Self {
+        Self {
+            fresnel: MetalFresnel::Complex { eta, k },
+            roughness: Arc::new(SolidColor::new(Float3::full(roughness))),
+        }
+    }
+
+    pub fn gold(roughness: f64) -> Self {
+        Self::conductor(
+            Float3::new(0.143, 0.374, 1.442),
+            Float3::new(3.983, 2.385, 1.603),
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f64) -> Self {
+        Self::conductor(
+            Float3::new(0.200, 0.924, 1.102),
+            Float3::new(3.912, 2.452, 2.142),
+            roughness,
+        )
+    }
+
+    pub fn aluminium(roughness: f64) -> Self {
+        Self::conductor(
+            Float3::new(1.657, 0.880, 0.521),
+            Float3::new(9.224, 6.270, 4.837),
+            roughness,
+        )
+    }
+
+    pub fn with_roughness(mut self, roughness: Arc<dyn Texture>) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    fn ggx(&self, hit: &HitInfo) -> Ggx {
+        Ggx::from_roughness(self.roughness.scalar_at(hit))
+    }
+
+    /// マイクロファセットの法線となす角の余弦がcos_iのときの反射率
+    fn fresnel(&self, cos_i: f64, hit: &HitInfo) -> Color {
+        match &self.fresnel {
+            MetalFresnel::Schlick(albedo) => schlick(albedo.value_at(hit), cos_i),
+            MetalFresnel::Complex { eta, k } => Float3::new(
+                fresnel_conductor(cos_i, eta.x(), k.x()),
+                fresnel_conductor(cos_i, eta.y(), k.y()),
+                fresnel_conductor(cos_i, eta.z(), k.z()),
+            ),
+        }
+    }
+}
+
+/// 垂直に入射したときの反射率f0から、Schlickの近似でフレネル反射率を求める
+fn schlick(f0: Color, cos_i: f64) -> Color {
+    f0 + (Float3::one() - f0) * (1.0 - cos_i).clamp(0.0, 1.0).powi(5)
 }
 
+/// 複素屈折率eta + ikの導体のフレネル反射率(偏光していない光について、s偏光とp偏光の平均)
+fn fresnel_conductor(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * cos_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rs + rp)
+}
+
+/// マテリアルの散乱はBSDFとして表す
+/// 方向はいずれも正規化済みで表面から外向きとし、woは視点(レイの来た方向)、wiは光の来る方向
+/// hit.nは常にwoの側を向いている
 pub trait Material: std::fmt::Debug + Sync + Send {
-    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo>;
+    /// BSDFの値(cosは含まない)。鏡面のローブは含まない
+    fn eval(&self, _wi: Vector3, _wo: Vector3, _hit: &HitInfo) -> Color {
+        Float3::zero()
+    }
+    /// woから散乱する方向wiを選ぶ。光を吸収した場合はNone
+    fn sample(&self, _wo: Vector3, _hit: &HitInfo) -> Option<BsdfSample> {
+        None
+    }
+    /// sampleがwiを選ぶ確率密度(立体角あたり)。鏡面のローブは含まない
+    fn pdf(&self, _wi: Vector3, _wo: Vector3, _hit: &HitInfo) -> f64 {
+        0.0
+    }
     fn emitted(&self, _ray: &Ray, _hit: &HitInfo) -> Float3 {
         Float3::zero()
     }
+    /// 光を放つかどうか。trueなら光源として直接サンプリングする
+    fn is_emissive(&self) -> bool {
+        false
+    }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
-        let target = hit.p + hit.n + Float3::randpm_unit_vector();
-        Some(ScatterInfo::new(
-            Ray::new(hit.p, target - hit.p),
-            self.albedo,
-        ))
+    fn eval(&self, wi: Vector3, _wo: Vector3, hit: &HitInfo) -> Color {
+        if wi.dot(hit.n) > 0.0 {
+            self.albedo.value_at(hit) * FRAC_1_PI
+        } else {
+            Float3::zero()
+        }
+    }
+
+    fn sample(&self, wo: Vector3, hit: &HitInfo) -> Option<BsdfSample> {
+        let wi = Float3::random_cosine_direction(hit.n);
+        let pdf = self.pdf(wi, wo, hit);
+        //cosに比例して選ぶので、BSDF x cos / pdfはalbedoになる
+        (pdf > 0.0).then(|| BsdfSample::new(wi, pdf, self.albedo.value_at(hit)))
+    }
+
+    fn pdf(&self, wi: Vector3, _wo: Vector3, hit: &HitInfo) -> f64 {
+        wi.dot(hit.n).max(0.0) * FRAC_1_PI
     }
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
-        let mut reflected = ray.direction.normalize().reflect(hit.n);
-        reflected = reflected + Float3::randpm_unit_vector() * self.fuzz;
-        if reflected.dot(hit.n) > 0.0 {
-            Some(ScatterInfo::new(Ray::new(hit.p, reflected), self.albedo))
-        } else {
-            None
+    fn eval(&self, wi: Vector3, wo: Vector3, hit: &HitInfo) -> Color {
+        let ggx = self.ggx(hit);
+        let frame = Frame::new(hit.n);
+        let (wi, wo) = (frame.to_local(wi), frame.to_local(wo));
+        if ggx.is_smooth() || wi.z() <= 0.0 || wo.z() <= 0.0 {
+            return Float3::zero();
+        }
+        let h = (wi + wo).normalize();
+        self.fresnel(wo.dot(h), hit) * (ggx.d(h) * ggx.g2(wo, wi) / (4.0 * wo.z() * wi.z()))
+    }
+
+    fn sample(&self, wo: Vector3, hit: &HitInfo) -> Option<BsdfSample> {
+        let ggx = self.ggx(hit);
+        if ggx.is_smooth() {
+            let cos_i = wo.dot(hit.n);
+            return Some(BsdfSample::specular(
+                (-wo).reflect(hit.n),
+                self.fresnel(cos_i, hit),
+            ));
+        }
+        let frame = Frame::new(hit.n);
+        let wo_local = frame.to_local(wo);
+        //見えているマイクロファセットの法線を選んで反射する
+        let h = ggx.sample_visible(wo_local);
+        let wi_local = (-wo_local).reflect(h);
+        if wi_local.z() <= 0.0 || wo_local.dot(h) <= 0.0 {
+            return None;
         }
+        let pdf = ggx.pdf_visible(wo_local, h) / (4.0 * wo_local.dot(h));
+        //BSDF x cos / pdf = F G2 / G1(wo)
+        let weight =
+            self.fresnel(wo_local.dot(h), hit) * (ggx.g2(wo_local, wi_local) / ggx.g1(wo_local));
+        Some(BsdfSample::new(frame.to_world(wi_local), pdf, weight))
+    }
+
+    fn pdf(&self, wi: Vector3, wo: Vector3, hit: &HitInfo) -> f64 {
+        let ggx = self.ggx(hit);
+        let frame = Frame::new(hit.n);
+        let (wi, wo) = (frame.to_local(wi), frame.to_local(wo));
+        if ggx.is_smooth() || wi.z() <= 0.0 || wo.z() <= 0.0 {
+            return 0.0;
+        }
+        let h = (wi + wo).normalize();
+        ggx.pdf_visible(wo, h) / (4.0 * wo.dot(h))
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct DiffuseLight {
-    emit: Float3,
+    emit: Arc<dyn Texture>,
+    two_sided: bool,
+    /// 光を放つかどうか。テクスチャの場合は常にtrueとする
+    emissive: bool,
 }
 impl DiffuseLight {
+    /// 両面から光る光源
     pub fn new(emit: Float3) -> Self {
-        Self { emit }
+        Self {
+            emissive: emit.length_squared() > 0.0,
+            ..Self::with_texture(Arc::new(SolidColor::new(emit)))
+        }
+    }
+
+    /// 表面(外向きの法線の側)からだけ光る光源
+    pub fn one_sided(emit: Float3) -> Self {
+        Self::new(emit).with_one_sided()
+    }
+
+    /// 場所によって明るさが変わる、両面から光る光源
+    pub fn with_texture(emit: Arc<dyn Texture>) -> Self {
+        Self {
+            emit,
+            two_sided: true,
+            emissive: true,
+        }
+    }
+
+    /// 表面からだけ光るようにする
+    pub fn with_one_sided(mut self) -> Self {
+        self.two_sided = false;
+        self
     }
 }
 impl Material for DiffuseLight {
-    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
-        None
+    fn emitted(&self, _ray: &Ray, hit: &HitInfo) -> Float3 {
+        if self.two_sided || hit.front_face {
+            self.emit.value_at(hit)
+        } else {
+            Float3::zero()
+        }
+    }
+    fn is_emissive(&self) -> bool {
+        self.emissive
+    }
+}
+
+/// ガラスや水などの誘電体
+/// 屈折率riの物体の内外でフレネルの式に従って反射と屈折を確率的に選ぶ
+/// absorptionを指定すると、物体の内部を進んだ距離に応じて光が吸収される(Beer-Lambertの法則)
+#[derive(Debug, Clone)]
+pub struct Dielectric {
+    ri: f64,
+    absorption: Float3,
+}
+
+impl Dielectric {
+    pub fn new(ri: f64) -> Self {
+        Self::with_absorption(ri, Float3::zero())
+    }
+
+    /// absorptionは単位距離あたりの吸収係数(RGBごと)
+    pub fn with_absorption(ri: f64, absorption: Float3) -> Self {
+        Self { ri, absorption }
+    }
+
+    /// 誘電体のフレネル反射率(偏光していない光について、s偏光とp偏光の平均)
+    /// cos_iは入射角の余弦、etaは入射側の屈折率 / 透過側の屈折率
+    fn fresnel(cos_i: f64, eta: f64) -> f64 {
+        let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+        if sin2_t >= 1.0 {
+            return 1.0;
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        let rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+        let rp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+        (rs * rs + rp * rp) * 0.5
+    }
+}
+
+impl Material for Dielectric {
+    fn sample(&self, wo: Vector3, hit: &HitInfo) -> Option<BsdfSample> {
+        //hit.nは常にレイの来た側を向いているので、内側から当たった場合は屈折率の比だけを逆にする
+        let normal = hit.n;
+        let eta = if hit.front_face {
+            self.ri.recip()
+        } else {
+            self.ri
+        };
+        let unit_direction = -wo;
+        let cos_i = wo.dot(normal).min(1.0);
+
+        //内側から出ていくときは、入ってから進んだ距離(レイの方向は正規化済みなのでhit.t)の分だけ吸収する
+        let albedo = if hit.front_face {
+            Float3::one()
+        } else {
+            (-self.absorption * hit.t).exp()
+        };
+
+        let direction = match unit_direction.refract(normal, eta) {
+            Some(refracted) if random::<f64>() >= Self::fresnel(cos_i, eta) => refracted,
+            //全反射、またはフレネル反射
+            _ => unit_direction.reflect(normal),
+        };
+        Some(BsdfSample::specular(direction, albedo))
+    }
+}
+
+/// 表面の粗い誘電体(すりガラスなど)
+/// GGXのマイクロファセットごとにフレネルの式で反射と屈折を選ぶ
+/// Walter et al. "Microfacet Models for Refraction through Rough Surfaces" (2007)
+#[derive(Debug, Clone)]
+pub struct RoughDielectric {
+    ri: f64,
+    roughness: Arc<dyn Texture>,
+}
+
+impl RoughDielectric {
+    /// roughnessはテクスチャの値をRGBの平均で数値にして使う
+    pub fn with_texture(ri: f64, roughness: Arc<dyn Texture>) -> Self {
+        Self { ri, roughness }
+    }
+
+    fn ggx(&self, hit: &HitInfo) -> Ggx {
+        Ggx::from_roughness(self.roughness.scalar_at(hit))
+    }
+
+    /// 透過側の屈折率 / woの側の屈折率
+    fn eta(&self, hit: &HitInfo) -> f64 {
+        if hit.front_face {
+            self.ri
+        } else {
+            self.ri.recip()
+        }
+    }
+
+    /// 局所座標系のwiとwoから、マイクロファセットの法線hとそれが反射かどうかを求める
+    /// hはwoの側を向く。wiとwoの組み合わせがあり得ない場合はNone
+    fn half_vector(wi: Vector3, wo: Vector3, eta: f64) -> Option<(Vector3, bool)> {
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let reflect = wi.z() > 0.0;
+        let h = if reflect { wi + wo } else { -(wo + wi * eta) };
+        if h.length_squared() <= 0.0 {
+            return None;
+        }
+        let h = h.normalize();
+        let h = if h.z() < 0.0 { -h } else { h };
+        //hから見てwoは表側、屈折ならwiは裏側になければならない
+        let valid = wo.dot(h) > 0.0 && (reflect || wi.dot(h) < 0.0);
+        valid.then_some((h, reflect))
+    }
+
+    /// 屈折の場合の、hの確率密度からwiの確率密度への変換(ヤコビアン)
+    fn refraction_jacobian(wi: Vector3, wo: Vector3, h: Vector3, eta: f64) -> f64 {
+        let denom = wo.dot(h) + eta * wi.dot(h);
+        eta * eta * wi.dot(h).abs() / (denom * denom)
+    }
+
+    /// 局所座標系でのBSDFの値(cosは含まない)と、それが反射かどうか
+    /// Principledの透過のローブでも使う
+    fn eval_local(ggx: &Ggx, eta: f64, wi: Vector3, wo: Vector3) -> Option<(f64, bool)> {
+        let (h, reflect) = Self::half_vector(wi, wo, eta)?;
+        let f = Dielectric::fresnel(wo.dot(h), eta.recip());
+        let dg = ggx.d(h) * ggx.g2(wo, wi);
+        let value = if reflect {
+            f * dg / (4.0 * wo.z() * wi.z())
+        } else {
+            (1.0 - f) * dg * wo.dot(h) * Self::refraction_jacobian(wi, wo, h, eta)
+                / (wo.z() * wi.z().abs())
+        };
+        Some((value, reflect))
+    }
+
+    /// 局所座標系でsample_localがwiを選ぶ確率密度
+    fn pdf_local(ggx: &Ggx, eta: f64, wi: Vector3, wo: Vector3) -> f64 {
+        let Some((h, reflect)) = Self::half_vector(wi, wo, eta) else {
+            return 0.0;
+        };
+        let f = Dielectric::fresnel(wo.dot(h), eta.recip());
+        let pdf_h = ggx.pdf_visible(wo, h);
+        if reflect {
+            f * pdf_h / (4.0 * wo.dot(h))
+        } else {
+            (1.0 - f) * pdf_h * Self::refraction_jacobian(wi, wo, h, eta)
+        }
+    }
+
+    /// 局所座標系でwoから散乱する方向を選ぶ
+    fn sample_local(ggx: &Ggx, eta: f64, wo: Vector3) -> Option<Vector3> {
+        let h = ggx.sample_visible(wo);
+        let cos_o = wo.dot(h);
+        if cos_o <= 0.0 {
+            return None;
+        }
+        //マイクロファセットで反射と屈折をフレネル反射率の確率で選ぶ
+        let f = Dielectric::fresnel(cos_o, eta.recip());
+        match (-wo).refract(h, eta.recip()) {
+            Some(refracted) if random::<f64>() >= f => (refracted.z() < 0.0).then_some(refracted),
+            _ => {
+                let reflected = (-wo).reflect(h);
+                (reflected.z() > 0.0).then_some(reflected)
+            }
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn eval(&self, wi: Vector3, wo: Vector3, hit: &HitInfo) -> Color {
+        let ggx = self.ggx(hit);
+        if ggx.is_smooth() {
+            return Float3::zero();
+        }
+        let frame = Frame::new(hit.n);
+        let (wi, wo) = (frame.to_local(wi), frame.to_local(wo));
+        match Self::eval_local(&ggx, self.eta(hit), wi, wo) {
+            Some((value, _)) => Float3::full(value),
+            None => Float3::zero(),
+        }
     }
-    fn emitted(&self, ray: &Ray, hit: &HitInfo) -> Float3 {
-        self.emit
+
+    fn sample(&self, wo: Vector3, hit: &HitInfo) -> Option<BsdfSample> {
+        let ggx = self.ggx(hit);
+        if ggx.is_smooth() {
+            return Dielectric::new(self.ri).sample(wo, hit);
+        }
+        let frame = Frame::new(hit.n);
+        let wo_local = frame.to_local(wo);
+        let wi_local = Self::sample_local(&ggx, self.eta(hit), wo_local)?;
+        let pdf = self.pdf(frame.to_world(wi_local), wo, hit);
+        if pdf <= 0.0 {
+            return None;
+        }
+        //選ぶ確率とフレネル反射率が打ち消し合い、BSDF x cos / pdf = G2 / G1(wo)
+        let weight = ggx.g2(wo_local, wi_local) / ggx.g1(wo_local);
+        Some(BsdfSample::new(
+            frame.to_world(wi_local),
+            pdf,
+            Float3::full(weight),
+        ))
+    }
+
+    fn pdf(&self, wi: Vector3, wo: Vector3, hit: &HitInfo) -> f64 {
+        let ggx = self.ggx(hit);
+        if ggx.is_smooth() {
+            return 0.0;
+        }
+        let frame = Frame::new(hit.n);
+        let (wi, wo) = (frame.to_local(wi), frame.to_local(wo));
+        Self::pdf_local(&ggx, self.eta(hit), wi, wo)
+    }
+}
+
+/// ベースの色、金属らしさ、粗さなどのパラメータで表す汎用のマテリアル(Disneyのprincipled BSDF)
+/// 拡散反射(とシーン)、光沢、透過のローブをエネルギーが増えないように重み付けして重ね、
+/// その上にクリアコートの層を載せる。パラメータはいずれもテクスチャで指定できる
+#[derive(Debug, Clone)]
+pub struct Principled {
+    base_color: Arc<dyn Texture>,
+    /// 0で誘電体、1で金属
+    metallic: Arc<dyn Texture>,
+    roughness: Arc<dyn Texture>,
+    /// 誘電体の垂直入射での反射率を0.08倍で表したもの。0.5で一般的な値(4%)になる
+    specular: Arc<dyn Texture>,
+    /// 布のような、浅い角度で明るくなる光沢の強さ
+    sheen: Arc<dyn Texture>,
+    clearcoat: Arc<dyn Texture>,
+    clearcoat_roughness: Arc<dyn Texture>,
+    /// 誘電体のうち、拡散反射の代わりに透過する割合
+    transmission: Arc<dyn Texture>,
+    /// 透過する場合の屈折率
+    ior: f64,
+}
+
+/// hitの点でのPrincipledのパラメータを、各ローブの重みに直したもの
+struct PrincipledLobes {
+    base: Color,
+    sheen: f64,
+    clearcoat: f64,
+    /// 誘電体の垂直入射での反射率
+    dielectric_f0: f64,
+    /// 光沢のローブの垂直入射での反射率。金属ならbaseになる
+    specular_f0: Color,
+    diffuse_weight: f64,
+    specular_weight: f64,
+    glass_weight: f64,
+    ggx: Ggx,
+    coat_ggx: Ggx,
+    /// 透過側の屈折率 / woの側の屈折率
+    eta: f64,
+}
+
+/// クリアコートの垂直入射での反射率(屈折率1.5)
+const CLEARCOAT_F0: f64 = 0.04;
+
+impl Principled {
+    pub fn new(base_color: Color) -> Self {
+        let constant =
+            |value: f64| -> Arc<dyn Texture> { Arc::new(SolidColor::new(Float3::full(value))) };
+        Self {
+            base_color: Arc::new(SolidColor::new(base_color)),
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            sheen: constant(0.0),
+            clearcoat: constant(0.0),
+            clearcoat_roughness: constant(0.1),
+            transmission: constant(0.0),
+            ior: 1.5,
+        }
+    }
+
+    pub fn with_base_color(mut self, base_color: Arc<dyn Texture>) -> Self {
+        self.base_color = base_color;
+        self
+    }
+
+    pub fn with_metallic(mut self, metallic: Arc<dyn Texture>) -> Self {
+        self.metallic = metallic;
+        self
+    }
+
+    pub fn with_roughness(mut self, roughness: Arc<dyn Texture>) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    pub fn with_specular(mut self, specular: Arc<dyn Texture>) -> Self {
+        self.specular = specular;
+        self
+    }
+
+    pub fn with_sheen(mut self, sheen: Arc<dyn Texture>) -> Self {
+        self.sheen = sheen;
+        self
+    }
+
+    pub fn with_clearcoat(mut self, clearcoat: Arc<dyn Texture>) -> Self {
+        self.clearcoat = clearcoat;
+        self
+    }
+
+    pub fn with_clearcoat_roughness(mut self, roughness: Arc<dyn Texture>) -> Self {
+        self.clearcoat_roughness = roughness;
+        self
+    }
+
+    pub fn with_transmission(mut self, transmission: Arc<dyn Texture>) -> Self {
+        self.transmission = transmission;
+        self
+    }
+
+    pub fn with_ior(mut self, ior: f64) -> Self {
+        self.ior = ior;
+        self
+    }
+
+    fn lobes(&self, hit: &HitInfo) -> PrincipledLobes {
+        let base = self.base_color.value_at(hit);
+        let metallic = self.metallic.scalar_at(hit).clamp(0.0, 1.0);
+        let transmission = self.transmission.scalar_at(hit).clamp(0.0, 1.0);
+        let dielectric_f0 = 0.08 * self.specular.scalar_at(hit).clamp(0.0, 1.0);
+        let dielectric = 1.0 - metallic;
+        PrincipledLobes {
+            base,
+            sheen: self.sheen.scalar_at(hit).max(0.0),
+            clearcoat: self.clearcoat.scalar_at(hit).clamp(0.0, 1.0),
+            dielectric_f0,
+            specular_f0: Float3::full(dielectric_f0).lerp(base, metallic),
+            //透過する部分は拡散反射と光沢の代わりに透過のローブ(反射も含む)を使う
+            diffuse_weight: dielectric * (1.0 - transmission),
+            specular_weight: 1.0 - dielectric * transmission,
+            glass_weight: dielectric * transmission,
+            ggx: Ggx::from_roughness(self.roughness.scalar_at(hit)),
+            coat_ggx: Ggx::from_roughness(self.clearcoat_roughness.scalar_at(hit)),
+            eta: if hit.front_face {
+                self.ior
+            } else {
+                self.ior.recip()
+            },
+        }
+    }
+}
+
+impl PrincipledLobes {
+    /// クリアコートで反射されずに下の層に届く割合
+    fn coat_transmittance(&self, cos: f64) -> f64 {
+        1.0 - self.clearcoat * schlick(Float3::full(CLEARCOAT_F0), cos).x()
+    }
+
+    /// クリアコートを往復して残る割合
+    fn layer(&self, wi: Vector3, wo: Vector3) -> f64 {
+        self.coat_transmittance(wo.z()) * self.coat_transmittance(wi.z().abs())
+    }
+
+    /// 拡散反射、光沢、透過、クリアコートのローブを選ぶ確率
+    /// woの方向から見たおおよその反射率に比例させる
+    fn probabilities(&self, wo: Vector3) -> Option<[f64; 4]> {
+        let cos_o = wo.z();
+        let fd = schlick(Float3::full(self.dielectric_f0), cos_o).x();
+        let fs = schlick(self.specular_f0, cos_o);
+        let weights = [
+            self.diffuse_weight * (1.0 - fd),
+            self.specular_weight * (fs.x() + fs.y() + fs.z()) / 3.0,
+            self.glass_weight,
+            self.clearcoat * schlick(Float3::full(CLEARCOAT_F0), cos_o).x(),
+        ];
+        let total: f64 = weights.iter().sum();
+        (total > 0.0).then(|| weights.map(|w| w / total))
+    }
+
+    /// 局所座標系でのBSDFの値(cosは含まない)。鏡面のローブは含まない
+    fn eval(&self, wi: Vector3, wo: Vector3) -> Color {
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return Float3::zero();
+        }
+        let mut value = Float3::zero();
+        if wi.z() > 0.0 {
+            let h = (wi + wo).normalize();
+            //鏡面反射で入らなかった光が拡散反射する。シーンは浅い角度で強くなる
+            let fd = |cos: f64| 1.0 - schlick(Float3::full(self.dielectric_f0), cos).x();
+            let sheen = self.sheen * (1.0 - wi.dot(h)).clamp(0.0, 1.0).powi(5);
+            let diffuse = self.base * (FRAC_1_PI * fd(wo.z()) * fd(wi.z())) + Float3::full(sheen);
+            value += diffuse * self.diffuse_weight;
+            if !self.ggx.is_smooth() {
+                let dg = self.ggx.d(h) * self.ggx.g2(wo, wi) / (4.0 * wo.z() * wi.z());
+                value += schlick(self.specular_f0, wo.dot(h)) * (dg * self.specular_weight);
+            }
+        }
+        if self.glass_weight > 0.0 && !self.ggx.is_smooth() {
+            if let Some((glass, reflect)) = RoughDielectric::eval_local(&self.ggx, self.eta, wi, wo)
+            {
+                value += self.glass_tint(reflect) * (glass * self.glass_weight);
+            }
+        }
+        let mut value = value * self.layer(wi, wo);
+        if wi.z() > 0.0 && self.clearcoat > 0.0 && !self.coat_ggx.is_smooth() {
+            let h = (wi + wo).normalize();
+            let coat = self.coat_ggx.d(h) * self.coat_ggx.g2(wo, wi) / (4.0 * wo.z() * wi.z());
+            value += schlick(Float3::full(CLEARCOAT_F0), wo.dot(h)) * (coat * self.clearcoat);
+        }
+        value
+    }
+
+    /// 透過のローブの色。物体に入るときと出るときの2回でbaseになるように平方根を掛ける
+    fn glass_tint(&self, reflect: bool) -> Color {
+        if reflect {
+            Float3::one()
+        } else {
+            self.base.sqrt()
+        }
+    }
+
+    /// 局所座標系でsampleがwiを選ぶ確率密度。鏡面のローブは含まない
+    fn pdf(&self, wi: Vector3, wo: Vector3) -> f64 {
+        let Some([p_diffuse, p_specular, p_glass, p_coat]) = self.probabilities(wo) else {
+            return 0.0;
+        };
+        let reflection_pdf = |ggx: &Ggx| {
+            if ggx.is_smooth() || wi.z() <= 0.0 {
+                return 0.0;
+            }
+            let h = (wi + wo).normalize();
+            ggx.pdf_visible(wo, h) / (4.0 * wo.dot(h))
+        };
+        let mut pdf = p_diffuse * wi.z().max(0.0) * FRAC_1_PI
+            + p_specular * reflection_pdf(&self.ggx)
+            + p_coat * reflection_pdf(&self.coat_ggx);
+        if p_glass > 0.0 && !self.ggx.is_smooth() {
+            pdf += p_glass * RoughDielectric::pdf_local(&self.ggx, self.eta, wi, wo);
+        }
+        pdf
+    }
+}
+
+impl Material for Principled {
+    fn eval(&self, wi: Vector3, wo: Vector3, hit: &HitInfo) -> Color {
+        let frame = Frame::new(hit.n);
+        self.lobes(hit).eval(frame.to_local(wi), frame.to_local(wo))
+    }
+
+    fn sample(&self, wo: Vector3, hit: &HitInfo) -> Option<BsdfSample> {
+        let frame = Frame::new(hit.n);
+        let lobes = self.lobes(hit);
+        let wo_local = frame.to_local(wo);
+        if wo_local.z() <= 0.0 {
+            return None;
+        }
+        let probabilities = lobes.probabilities(wo_local)?;
+        //ローブを1つ選び、鏡面ならそのローブだけで、そうでなければすべてのローブを合わせた確率密度で重みを求める
+        let mut u = random::<f64>();
+        let lobe = probabilities
+            .iter()
+            .position(|&p| {
+                u -= p;
+                u < 0.0
+            })
+            .unwrap_or(3);
+        let p = probabilities[lobe];
+        let specular = |wi: Vector3, weight: Color| {
+            let weight = weight * (lobes.layer(wi, wo_local) / p);
+            Some(BsdfSample::specular(frame.to_world(wi), weight))
+        };
+        let wi_local = match lobe {
+            0 => frame.to_local(Float3::random_cosine_direction(hit.n)),
+            1 | 3 => {
+                let ggx = if lobe == 1 {
+                    &lobes.ggx
+                } else {
+                    &lobes.coat_ggx
+                };
+                if ggx.is_smooth() {
+                    let wi = (-wo_local).reflect(Float3::zaxis());
+                    return if lobe == 1 {
+                        specular(
+                            wi,
+                            schlick(lobes.specular_f0, wo_local.z()) * lobes.specular_weight,
+                        )
+                    } else {
+                        //クリアコートは一番上の層なので減衰しない
+                        let f = schlick(Float3::full(CLEARCOAT_F0), wo_local.z());
+                        Some(BsdfSample::specular(
+                            frame.to_world(wi),
+                            f * (lobes.clearcoat / p),
+                        ))
+                    };
+                }
+                (-wo_local).reflect(ggx.sample_visible(wo_local))
+            }
+            _ => {
+                if lobes.ggx.is_smooth() {
+                    //滑らかなガラスと同じく、反射と屈折をフレネル反射率の確率で選ぶ
+                    let f = Dielectric::fresnel(wo_local.z(), lobes.eta.recip());
+                    let normal = Float3::zaxis();
+                    let (wi, reflect) = match (-wo_local).refract(normal, lobes.eta.recip()) {
+                        Some(refracted) if random::<f64>() >= f => (refracted, false),
+                        _ => ((-wo_local).reflect(normal), true),
+                    };
+                    return specular(wi, lobes.glass_tint(reflect) * lobes.glass_weight);
+                }
+                RoughDielectric::sample_local(&lobes.ggx, lobes.eta, wo_local)?
+            }
+        };
+        let pdf = lobes.pdf(wi_local, wo_local);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let weight = lobes.eval(wi_local, wo_local) * (wi_local.z().abs() / pdf);
+        Some(BsdfSample::new(frame.to_world(wi_local), pdf, weight))
+    }
+
+    fn pdf(&self, wi: Vector3, wo: Vector3, hit: &HitInfo) -> f64 {
+        let frame = Frame::new(hit.n);
+        self.lobes(hit).pdf(frame.to_local(wi), frame.to_local(wo))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rayt::math::{PI, PI2};
+
+    /// 方向の分布を比べるヒストグラムの分割数。cosθは-1から1、φは0から2π
+    const COS_BINS: usize = 16;
+    const PHI_BINS: usize = 32;
+    /// 各ビンの中で確率密度を積分するときの分割数
+    const QUADRATURE: usize = 12;
+    const SAMPLES: usize = 100_000;
+
+    fn solid(value: f64) -> Arc<dyn Texture> {
+        Arc::new(SolidColor::new(Float3::full(value)))
+    }
+
+    /// 法線が+zの点にwoの方向から当たった交差。frontがfalseなら物体の内側から当たったことにする
+    fn hit_from(material: Arc<dyn Material>, wo: Vector3, front: bool) -> HitInfo {
+        let outward = if front {
+            Float3::zaxis()
+        } else {
+            -Float3::zaxis()
+        };
+        let ray = Ray::new(wo, -wo);
+        HitInfo::new(
+            1.0,
+            Float3::zero(),
+            outward,
+            (0.5, 0.5),
+            1.0,
+            &ray,
+            material,
+        )
+    }
+
+    fn direction(cos_theta: f64, phi: f64) -> Vector3 {
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        Float3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+    }
+
+    fn bin(wi: Vector3) -> usize {
+        let c = (((wi.z() + 1.0) * 0.5 * COS_BINS as f64) as usize).min(COS_BINS - 1);
+        let phi = wi.y().atan2(wi.x()).rem_euclid(PI2);
+        let p = ((phi / PI2 * PHI_BINS as f64) as usize).min(PHI_BINS - 1);
+        c * PHI_BINS + p
+    }
+
+    /// ビンの範囲のcosθに対応するθの範囲
+    fn theta_range(c: usize) -> (f64, f64) {
+        let cos = |i: usize| -1.0 + 2.0 * i as f64 / COS_BINS as f64;
+        (
+            cos(c + 1).clamp(-1.0, 1.0).acos(),
+            cos(c).clamp(-1.0, 1.0).acos(),
+        )
+    }
+
+    /// 各ビンでのpdfの積分
+    /// 屈折や光沢のローブは極の近くに鋭いピークを持つことがあるので、cosθではなくθで分割して積分する
+    fn bin_integrals(material: &dyn Material, wo: Vector3, hit: &HitInfo) -> Vec<f64> {
+        let dp = PI2 / PHI_BINS as f64;
+        let mut integrals = vec![0.0; COS_BINS * PHI_BINS];
+        for c in 0..COS_BINS {
+            let (theta0, theta1) = theta_range(c);
+            let dt = (theta1 - theta0) / QUADRATURE as f64;
+            for p in 0..PHI_BINS {
+                let mut sum = 0.0;
+                for i in 0..QUADRATURE {
+                    let theta = theta0 + (i as f64 + 0.5) * dt;
+                    for j in 0..QUADRATURE {
+                        let phi = (p as f64 + (j as f64 + 0.5) / QUADRATURE as f64) * dp;
+                        sum += material.pdf(direction(theta.cos(), phi), wo, hit) * theta.sin();
+                    }
+                }
+                integrals[c * PHI_BINS + p] = sum * dt * dp / QUADRATURE as f64;
+            }
+        }
+        integrals
+    }
+
+    /// pdfの全立体角での積分をモンテカルロ法で求める
+    /// (θ, φ)を格子に区切り、各区画から1つずつランダムに方向を選ぶ
+    fn pdf_integral(material: &dyn Material, wo: Vector3, hit: &HitInfo) -> f64 {
+        let (nt, np) = (256, 128);
+        let (dt, dp) = (PI / nt as f64, PI2 / np as f64);
+        let mut sum = 0.0;
+        for i in 0..nt {
+            for j in 0..np {
+                let theta = (i as f64 + random::<f64>()) * dt;
+                let phi = (j as f64 + random::<f64>()) * dp;
+                sum += material.pdf(direction(theta.cos(), phi), wo, hit) * theta.sin();
+            }
+        }
+        sum * dt * dp
+    }
+
+    /// sampleが返す方向の分布がpdfと一致することを確かめる
+    /// - 各サンプルのpdfとweightが、pdfとevalから求めた値と一致する
+    /// - 方向のヒストグラムが、pdfを積分して求めた期待値とカイ2乗検定で一致する
+    /// - pdfの積分が、鏡面でないサンプルが得られる確率(何も失わなければ1)と一致する
+    fn check_sampling(material: Arc<dyn Material>, wo: Vector3, front: bool) {
+        let wo = wo.normalize();
+        let hit = hit_from(material.clone(), wo, front);
+        let mut histogram = vec![0.0; COS_BINS * PHI_BINS];
+        let mut accepted = 0;
+        for _ in 0..SAMPLES {
+            let Some(sample) = material.sample(wo, &hit) else {
+                continue;
+            };
+            if sample.specular {
+                continue;
+            }
+            accepted += 1;
+            let pdf = material.pdf(sample.wi, wo, &hit);
+            assert!(
+                (sample.pdf - pdf).abs() <= 1e-6 * pdf,
+                "{material:?}: sample pdf {} but pdf() {} for {:?}",
+                sample.pdf,
+                pdf,
+                sample.wi
+            );
+            let expected =
+                material.eval(sample.wi, wo, &hit) * (sample.wi.dot(hit.n).abs() / sample.pdf);
+            assert!(
+                (sample.weight - expected).length() <= 1e-6 * (1.0 + expected.length()),
+                "{material:?}: weight {:?} but eval * cos / pdf {:?}",
+                sample.weight,
+                expected
+            );
+            histogram[bin(sample.wi)] += 1.0;
+        }
+
+        //期待値の小さいビンはまとめてから比べる
+        let integrals = bin_integrals(material.as_ref(), wo, &hit);
+        let (mut chi2, mut dof) = (0.0, 0);
+        let (mut pooled_observed, mut pooled_expected) = (0.0, 0.0);
+        for (observed, integral) in histogram.iter().zip(&integrals) {
+            let expected = integral * SAMPLES as f64;
+            if expected < 5.0 {
+                pooled_observed += observed;
+                pooled_expected += expected;
+            } else {
+                chi2 += (observed - expected).powi(2) / expected;
+                dof += 1;
+            }
+        }
+        if pooled_expected >= 5.0 {
+            chi2 += (pooled_observed - pooled_expected).powi(2) / pooled_expected;
+            dof += 1;
+        } else {
+            assert!(
+                pooled_observed <= 20.0,
+                "{material:?}: {pooled_observed} samples where pdf is almost 0"
+            );
+        }
+        //自由度kのカイ2乗分布は平均k、標準偏差sqrt(2k)。ビンの中の積分の誤差もあるので余裕を持たせる
+        let dof = dof as f64;
+        assert!(
+            chi2 < dof + 6.0 * (2.0 * dof).sqrt(),
+            "{material:?}: chi2 {chi2} with {dof} bins for wo {wo:?}"
+        );
+
+        let fraction = accepted as f64 / SAMPLES as f64;
+        let integral = pdf_integral(material.as_ref(), wo, &hit);
+        assert!(
+            (integral - fraction).abs() < 0.01,
+            "{material:?}: pdf integrates to {integral} but {fraction} of samples are not specular"
+        );
+        let quadrature: f64 = integrals.iter().sum();
+        assert!(
+            (quadrature - fraction).abs() < 0.01,
+            "{material:?}: pdf integrates to {quadrature} but {fraction} of samples are not specular"
+        );
+    }
+
+    #[test]
+    fn lambertian_sampling_matches_pdf() {
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new(Float3::new(0.8, 0.5, 0.2)));
+        for wo in [Float3::new(0.0, 0.0, 1.0), Float3::new(0.8, 0.3, 0.2)] {
+            check_sampling(material.clone(), wo, true);
+        }
+        //拡散反射は失う方向が無いので、pdfの積分は1になる
+        let wo = Float3::new(0.3, -0.2, 0.9).normalize();
+        let hit = hit_from(material.clone(), wo, true);
+        assert!((pdf_integral(material.as_ref(), wo, &hit) - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn metal_sampling_matches_pdf() {
+        let materials: [Arc<dyn Material>; 2] = [
+            Arc::new(Metal::new(Float3::new(0.9, 0.6, 0.3), 0.5)),
+            Arc::new(Metal::gold(0.7)),
+        ];
+        for material in materials {
+            for wo in [Float3::new(0.0, 0.1, 1.0), Float3::new(0.9, 0.2, 0.35)] {
+                check_sampling(material.clone(), wo, true);
+            }
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_sampling_matches_pdf() {
+        let material: Arc<dyn Material> = Arc::new(RoughDielectric::with_texture(1.5, solid(0.6)));
+        for wo in [Float3::new(0.0, 0.1, 1.0), Float3::new(0.7, 0.2, 0.5)] {
+            //外側から入る場合と、内側から出る場合(全反射を含む)
+            check_sampling(material.clone(), wo, true);
+            check_sampling(material.clone(), wo, false);
+        }
+    }
+
+    #[test]
+    fn principled_sampling_matches_pdf() {
+        let base =
+            || Principled::new(Float3::new(0.8, 0.4, 0.2)).with_clearcoat_roughness(solid(0.5));
+        let materials: [Arc<dyn Material>; 4] = [
+            Arc::new(base()),
+            Arc::new(base().with_metallic(solid(1.0)).with_roughness(solid(0.6))),
+            Arc::new(
+                base()
+                    .with_transmission(solid(0.7))
+                    .with_roughness(solid(0.6))
+                    .with_sheen(solid(0.5))
+                    .with_clearcoat(solid(0.8)),
+            ),
+            //滑らかなガラスとクリアコートのデルタ関数のローブを含む場合でも、残りのローブの分布は一致する
+            Arc::new(
+                base()
+                    .with_transmission(solid(0.5))
+                    .with_roughness(solid(0.0))
+                    .with_clearcoat(solid(1.0))
+                    .with_clearcoat_roughness(solid(0.0)),
+            ),
+        ];
+        for material in materials {
+            for wo in [Float3::new(0.0, 0.1, 1.0), Float3::new(0.8, -0.3, 0.3)] {
+                check_sampling(material.clone(), wo, true);
+            }
+        }
     }
 }
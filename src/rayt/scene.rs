@@ -0,0 +1,1124 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use image::ImageError;
+use serde::Deserialize;
+
+use super::camera::{Aperture, Camera, Projection};
+use super::environment::{ConstantEnvironment, Environment, EnvironmentMap, SkyGradient};
+use super::float3::{Color, Float3, Point3, Vector3};
+use super::image_texture::{ColorSpace, Filter, ImageTexture, Wrap};
+use super::instance::Instance;
+use super::light::{DirectionalLight, Light, PointLight, SpotLight};
+use super::matrix::Matrix4;
+use super::mesh::Triangle;
+use super::motion::Keyframes;
+use super::noise::{Perlin, Worley};
+use super::obj::{load_obj, ObjError};
+use super::quaternion::Quaternion;
+use super::render::{
+    Dielectric, DiffuseLight, Lambertian, Material, Metal, Principled, RoughDielectric,
+};
+use super::shape::{Box3D, MovingSphere, Rect, RectAxisType, Shape, ShapeList, Sphere};
+use super::sky::PreethamSky;
+use super::texture::{
+    Checker, Marble, NoiseTexture, SolidColor, Texture, Turbulence, UvChecker, UvColor, Wood,
+    WorleyTexture,
+};
+
+/// シーンファイル(TOML)の読み込みエラー
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    Invalid {
+        path: PathBuf,
+        message: String,
+    },
+    Obj(ObjError),
+    Image {
+        path: PathBuf,
+        source: ImageError,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Parse { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Invalid { path, message } => write!(f, "{}: {}", path.display(), message),
+            SceneError::Obj(e) => e.fmt(f),
+            SceneError::Image { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Parse { source, .. } => Some(source),
+            SceneError::Invalid { .. } => None,
+            SceneError::Obj(e) => Some(e),
+            SceneError::Image { source, .. } => Some(source),
+        }
+    }
+}
+
+impl From<ObjError> for SceneError {
+    fn from(e: ObjError) -> Self {
+        SceneError::Obj(e)
+    }
+}
+
+/// シーンファイルのカメラの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDesc {
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    #[serde(default = "default_vup")]
+    pub vup: Vector3,
+    pub vfov: f64,
+    /// レンズの半径。0ならピンホールカメラ
+    #[serde(default)]
+    pub aperture: f64,
+    /// ピントが合う距離。立体視では左右の視差が0になる距離にもなる。省略するとlookatまでの距離
+    #[serde(default)]
+    pub focus_distance: Option<f64>,
+    /// 絞りの羽根の枚数。指定すると絞りが正多角形になる
+    #[serde(default)]
+    pub blades: Option<u32>,
+    /// 絞りの多角形の回転角(度)
+    #[serde(default)]
+    pub blade_rotation: f64,
+    /// シャッターが開いている時刻の範囲。動く形状にモーションブラーが付く
+    #[serde(default)]
+    pub shutter_open: f64,
+    #[serde(default)]
+    pub shutter_close: f64,
+    /// 投影方法。省略すると透視投影
+    #[serde(default)]
+    pub projection: Projection,
+    /// 立体視の左右の目の間隔。指定すると画像の上半分に左目、下半分に右目の画像を出力する
+    #[serde(default)]
+    pub stereo: Option<f64>,
+}
+
+fn default_vup() -> Vector3 {
+    Float3::yaxis()
+}
+
+impl CameraDesc {
+    /// ピンホールカメラの設定
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vector3, vfov: f64) -> Self {
+        Self {
+            lookfrom,
+            lookat,
+            vup,
+            vfov,
+            aperture: 0.0,
+            focus_distance: None,
+            blades: None,
+            blade_rotation: 0.0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            projection: Projection::Perspective,
+            stereo: None,
+        }
+    }
+
+    /// aspectは出力する画像全体の縦横比
+    pub fn build(&self, aspect: f64) -> Camera {
+        //立体視では片目の画像の高さが半分になる
+        let aspect = if self.stereo.is_some() {
+            aspect * 2.0
+        } else {
+            aspect
+        };
+        let mut camera =
+            Camera::from_lookat(self.lookfrom, self.lookat, self.vup, self.vfov, aspect)
+                .with_shutter(self.shutter_open, self.shutter_close)
+                .with_projection(self.projection);
+        let focus_distance = self
+            .focus_distance
+            .unwrap_or_else(|| (self.lookat - self.lookfrom).length());
+        //立体視の視差はピントが合う距離で0にする
+        if let Some(eye_separation) = self.stereo {
+            camera = camera.with_stereo(eye_separation, focus_distance);
+        }
+        if self.aperture <= 0.0 {
+            return camera;
+        }
+        let aperture = match self.blades {
+            Some(blades) => Aperture::Polygon {
+                blades,
+                rotation: self.blade_rotation,
+            },
+            None => Aperture::Circle,
+        };
+        camera
+            .with_lens(self.aperture, focus_distance)
+            .with_aperture(aperture)
+    }
+}
+
+/// シーンファイルのレンダリングの設定。省略した値はコマンドラインのデフォルトを使う
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenderDesc {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub samples: Option<usize>,
+    pub depth: Option<usize>,
+}
+
+/// どの物体にも当たらなかったレイに届く光
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum EnvironmentDesc {
+    Constant {
+        color: Color,
+    },
+    /// 真下のbottomから真上のtopに変わるグラデーション。省略すると白から水色になる
+    Gradient {
+        #[serde(default = "default_sky_bottom")]
+        bottom: Color,
+        #[serde(default = "default_sky_top")]
+        top: Color,
+    },
+    /// 正距円筒図法の画像(HDR、OpenEXRなど)
+    Map {
+        path: PathBuf,
+        /// y軸周りの回転(度)
+        #[serde(default)]
+        rotation: f64,
+        intensity: Option<f64>,
+    },
+    /// Preethamの解析的な空と太陽。elevationは太陽の高度、azimuthは方位角(度)
+    /// 方位角は0で+z、90で-xの方向。turbidityは大気の濁り具合で、省略すると3になる
+    Sky {
+        elevation: f64,
+        #[serde(default)]
+        azimuth: f64,
+        #[serde(default = "default_turbidity")]
+        turbidity: f64,
+        intensity: Option<f64>,
+    },
+}
+
+fn default_turbidity() -> f64 {
+    3.0
+}
+
+fn default_sky_bottom() -> Color {
+    Float3::one()
+}
+
+fn default_sky_top() -> Color {
+    Float3::new(0.5, 0.7, 1.0)
+}
+
+impl EnvironmentDesc {
+    /// 画像のパスはbase_dirからの相対パス
+    fn build(&self, base_dir: &Path) -> Result<Arc<dyn Environment>, SceneError> {
+        let environment: Arc<dyn Environment> = match self {
+            EnvironmentDesc::Constant { color } => Arc::new(ConstantEnvironment::new(*color)),
+            EnvironmentDesc::Gradient { bottom, top } => Arc::new(SkyGradient::new(*bottom, *top)),
+            EnvironmentDesc::Map {
+                path,
+                rotation,
+                intensity,
+            } => {
+                let path = base_dir.join(path);
+                let map = EnvironmentMap::load(&path)
+                    .map_err(|source| SceneError::Image { path, source })?;
+                Arc::new(
+                    map.with_rotation(*rotation)
+                        .with_intensity(intensity.unwrap_or(1.0)),
+                )
+            }
+            EnvironmentDesc::Sky {
+                elevation,
+                azimuth,
+                turbidity,
+                intensity,
+            } => Arc::new(
+                PreethamSky::new(*elevation, *azimuth, *turbidity)
+                    .with_intensity(intensity.unwrap_or(1.0)),
+            ),
+        };
+        Ok(environment)
+    }
+}
+
+/// 大きさを持たない光源
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum LightDesc {
+    /// intensityは放射強度(単位立体角あたりの光の強さ)
+    Point { position: Point3, intensity: Color },
+    /// inner、outerは照らす方向からの角度(度)
+    Spot {
+        position: Point3,
+        direction: Vector3,
+        intensity: Color,
+        inner: f64,
+        outer: f64,
+    },
+    /// directionは光の進む向き、irradianceは光に垂直な面が受ける放射照度
+    Directional {
+        direction: Vector3,
+        irradiance: Color,
+    },
+}
+
+impl LightDesc {
+    fn build(&self) -> Result<Arc<dyn Light>, String> {
+        let nonzero = |v: &Vector3, name: &str| {
+            if v.length_squared() > 0.0 {
+                Ok(())
+            } else {
+                Err(format!("{} must not be zero", name))
+            }
+        };
+        let light: Arc<dyn Light> = match self {
+            LightDesc::Point {
+                position,
+                intensity,
+            } => Arc::new(PointLight::new(*position, *intensity)),
+            LightDesc::Spot {
+                position,
+                direction,
+                intensity,
+                inner,
+                outer,
+            } => {
+                nonzero(direction, "direction")?;
+                if inner > outer {
+                    return Err("inner must not be larger than outer".to_string());
+                }
+                Arc::new(SpotLight::new(
+                    *position, *direction, *intensity, *inner, *outer,
+                ))
+            }
+            LightDesc::Directional {
+                direction,
+                irradiance,
+            } => {
+                nonzero(direction, "direction")?;
+                Arc::new(DirectionalLight::new(*direction, *irradiance))
+            }
+        };
+        Ok(light)
+    }
+}
+
+/// 名前付きのテクスチャ
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Solid {
+        color: Color,
+    },
+    /// 空間を一辺sizeの立方体に区切った市松模様
+    Checker {
+        even: Color,
+        odd: Color,
+        size: f64,
+    },
+    /// テクスチャ座標をu方向にwidth個、v方向にheight個に区切った市松模様
+    UvChecker {
+        even: Color,
+        odd: Color,
+        width: u32,
+        height: u32,
+    },
+    /// テクスチャ座標を色で表す
+    Uv,
+    /// Perlinノイズ。octavesが2以上ならfBm
+    /// 以下のノイズの模様では、colorsに[値が小さい側の色, 大きい側の色]を指定できる
+    Noise {
+        scale: f64,
+        octaves: Option<u32>,
+        #[serde(default)]
+        seed: u64,
+        colors: Option<[Color; 2]>,
+    },
+    Turbulence {
+        scale: f64,
+        octaves: Option<u32>,
+        #[serde(default)]
+        seed: u64,
+        colors: Option<[Color; 2]>,
+    },
+    /// 大理石。colorsは[地の色, 筋の色]
+    Marble {
+        scale: f64,
+        octaves: Option<u32>,
+        strength: Option<f64>,
+        #[serde(default)]
+        seed: u64,
+        colors: Option<[Color; 2]>,
+    },
+    /// 木目。scaleは距離1あたりの年輪の数、colorsは[明るい色, 暗い色]
+    Wood {
+        scale: f64,
+        octaves: Option<u32>,
+        strength: Option<f64>,
+        #[serde(default)]
+        seed: u64,
+        colors: Option<[Color; 2]>,
+    },
+    /// Worleyのセルラーノイズ
+    Worley {
+        scale: f64,
+        #[serde(default)]
+        seed: u64,
+        colors: Option<[Color; 2]>,
+    },
+    /// PNG、JPEG、HDRの画像。パスはシーンファイルからの相対パス
+    /// 色の画像はcolor_space = "srgb"(デフォルト)、粗さなどの数値の画像は"linear"にする
+    Image {
+        path: PathBuf,
+        #[serde(default)]
+        filter: Filter,
+        #[serde(default)]
+        wrap: Wrap,
+        #[serde(default)]
+        color_space: ColorSpace,
+    },
+}
+
+impl TextureDesc {
+    /// 画像のパスはシーンファイルのあるディレクトリからの相対パスとする
+    fn build(&self, builder: &ShapeBuilder, context: &str) -> Result<Arc<dyn Texture>, SceneError> {
+        let solid = |color: Color| -> Arc<dyn Texture> { Arc::new(SolidColor::new(color)) };
+        let texture = match *self {
+            TextureDesc::Solid { color } => solid(color),
+            TextureDesc::Checker { even, odd, size } => {
+                if size <= 0.0 {
+                    return Err(builder.invalid(context, "size must be positive"));
+                }
+                Arc::new(Checker::new(solid(even), solid(odd), size))
+            }
+            TextureDesc::UvChecker {
+                even,
+                odd,
+                width,
+                height,
+            } => Arc::new(UvChecker::new(solid(even), solid(odd), width, height)),
+            TextureDesc::Uv => Arc::new(UvColor),
+            TextureDesc::Noise {
+                scale,
+                octaves,
+                seed,
+                colors,
+            } => {
+                let mut noise = NoiseTexture::new(Perlin::new(seed), scale);
+                if let Some(octaves) = octaves {
+                    noise = noise.with_octaves(octaves);
+                }
+                if let Some([low, high]) = colors {
+                    noise = noise.with_colors(low, high);
+                }
+                Arc::new(noise)
+            }
+            TextureDesc::Turbulence {
+                scale,
+                octaves,
+                seed,
+                colors,
+            } => {
+                let mut turbulence = Turbulence::new(Perlin::new(seed), scale);
+                if let Some(octaves) = octaves {
+                    turbulence = turbulence.with_octaves(octaves);
+                }
+                if let Some([low, high]) = colors {
+                    turbulence = turbulence.with_colors(low, high);
+                }
+                Arc::new(turbulence)
+            }
+            TextureDesc::Marble {
+                scale,
+                octaves,
+                strength,
+                seed,
+                colors,
+            } => {
+                let mut marble = Marble::new(Perlin::new(seed), scale);
+                if let Some(octaves) = octaves {
+                    marble = marble.with_octaves(octaves);
+                }
+                if let Some(strength) = strength {
+                    marble = marble.with_strength(strength);
+                }
+                if let Some([base, vein]) = colors {
+                    marble = marble.with_colors(base, vein);
+                }
+                Arc::new(marble)
+            }
+            TextureDesc::Wood {
+                scale,
+                octaves,
+                strength,
+                seed,
+                colors,
+            } => {
+                let mut wood = Wood::new(Perlin::new(seed), scale);
+                if let Some(octaves) = octaves {
+                    wood = wood.with_octaves(octaves);
+                }
+                if let Some(strength) = strength {
+                    wood = wood.with_strength(strength);
+                }
+                if let Some([light, dark]) = colors {
+                    wood = wood.with_colors(light, dark);
+                }
+                Arc::new(wood)
+            }
+            TextureDesc::Worley {
+                scale,
+                seed,
+                colors,
+            } => {
+                let mut worley = WorleyTexture::new(Worley::new(seed), scale);
+                if let Some([low, high]) = colors {
+                    worley = worley.with_colors(low, high);
+                }
+                Arc::new(worley)
+            }
+            TextureDesc::Image {
+                ref path,
+                filter,
+                wrap,
+                color_space,
+            } => {
+                let path = builder.base_dir.join(path);
+                let image = ImageTexture::load(&path, color_space)
+                    .map_err(|source| SceneError::Image { path, source })?;
+                Arc::new(image.with_filter(filter).with_wrap(wrap))
+            }
+        };
+        Ok(texture)
+    }
+}
+
+/// マテリアルの色。[r, g, b]か、[textures]で定義したテクスチャの名前で指定する
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ColorDesc {
+    Color(Color),
+    Texture(String),
+}
+
+/// マテリアルの数値。数値か、[textures]で定義したテクスチャの名前で指定する
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ValueDesc {
+    Value(f64),
+    Texture(String),
+}
+
+type Textures<'a> = HashMap<&'a str, Arc<dyn Texture>>;
+
+fn find_texture(textures: &Textures, name: &str) -> Result<Arc<dyn Texture>, String> {
+    textures
+        .get(name)
+        .map(Arc::clone)
+        .ok_or_else(|| format!("unknown texture `{}`", name))
+}
+
+impl ColorDesc {
+    fn build(&self, textures: &Textures) -> Result<Arc<dyn Texture>, String> {
+        match self {
+            ColorDesc::Color(color) => Ok(Arc::new(SolidColor::new(*color))),
+            ColorDesc::Texture(name) => find_texture(textures, name),
+        }
+    }
+}
+
+impl ValueDesc {
+    fn build(&self, textures: &Textures) -> Result<Arc<dyn Texture>, String> {
+        match self {
+            ValueDesc::Value(value) => Ok(Arc::new(SolidColor::new(Float3::full(*value)))),
+            ValueDesc::Texture(name) => find_texture(textures, name),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        albedo: ColorDesc,
+    },
+    /// albedoは垂直に入射したときの反射率
+    Metal {
+        albedo: ColorDesc,
+        roughness: ValueDesc,
+    },
+    /// 複素屈折率eta + ikの導体。presetを指定するとその金属の値を使う
+    Conductor {
+        preset: Option<ConductorPreset>,
+        eta: Option<Color>,
+        k: Option<Color>,
+        roughness: ValueDesc,
+    },
+    DiffuseLight {
+        emit: ColorDesc,
+        /// trueなら表面からだけ光る
+        #[serde(default)]
+        one_sided: bool,
+    },
+    Dielectric {
+        ri: f64,
+        absorption: Option<Color>,
+    },
+    /// 表面の粗い誘電体
+    RoughDielectric {
+        ri: f64,
+        roughness: ValueDesc,
+    },
+    /// パラメータで表す汎用のマテリアル。省略したパラメータはPrincipled::newの値になる
+    Principled {
+        base_color: Option<ColorDesc>,
+        metallic: Option<ValueDesc>,
+        roughness: Option<ValueDesc>,
+        specular: Option<ValueDesc>,
+        sheen: Option<ValueDesc>,
+        clearcoat: Option<ValueDesc>,
+        clearcoat_roughness: Option<ValueDesc>,
+        transmission: Option<ValueDesc>,
+        ior: Option<f64>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ConductorPreset {
+    Gold,
+    Copper,
+    Aluminium,
+}
+
+impl MaterialDesc {
+    /// 知らないテクスチャの名前を指定していたらエラーメッセージを返す
+    fn build(&self, textures: &Textures) -> Result<Arc<dyn Material>, String> {
+        let material: Arc<dyn Material> = match self {
+            MaterialDesc::Lambertian { albedo } => {
+                Arc::new(Lambertian::with_texture(albedo.build(textures)?))
+            }
+            MaterialDesc::Metal { albedo, roughness } => Arc::new(Metal::with_textures(
+                albedo.build(textures)?,
+                roughness.build(textures)?,
+            )),
+            MaterialDesc::Conductor {
+                preset,
+                eta,
+                k,
+                roughness,
+            } => {
+                let metal = match (preset, eta, k) {
+                    (Some(ConductorPreset::Gold), None, None) => Metal::gold(0.0),
+                    (Some(ConductorPreset::Copper), None, None) => Metal::copper(0.0),
+                    (Some(ConductorPreset::Aluminium), None, None) => Metal::aluminium(0.0),
+                    (None, Some(eta), Some(k)) => Metal::conductor(*eta, *k, 0.0),
+                    _ => return Err("conductor needs either preset or both eta and k".to_string()),
+                };
+                Arc::new(metal.with_roughness(roughness.build(textures)?))
+            }
+            MaterialDesc::DiffuseLight { emit, one_sided } => {
+                //単色なら明るさが0かどうかを判定できるように、テクスチャを使わずに作る
+                let light = match emit {
+                    ColorDesc::Color(color) => DiffuseLight::new(*color),
+                    ColorDesc::Texture(_) => DiffuseLight::with_texture(emit.build(textures)?),
+                };
+                Arc::new(if *one_sided {
+                    light.with_one_sided()
+                } else {
+                    light
+                })
+            }
+            MaterialDesc::Dielectric { ri, absorption } => Arc::new(Dielectric::with_absorption(
+                *ri,
+                absorption.unwrap_or(Float3::zero()),
+            )),
+            MaterialDesc::RoughDielectric { ri, roughness } => Arc::new(
+                RoughDielectric::with_texture(*ri, roughness.build(textures)?),
+            ),
+            MaterialDesc::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                sheen,
+                clearcoat,
+                clearcoat_roughness,
+                transmission,
+                ior,
+            } => {
+                let mut material = Principled::new(Float3::full(0.8));
+                if let Some(base_color) = base_color {
+                    material = material.with_base_color(base_color.build(textures)?);
+                }
+                //指定されたパラメータだけテクスチャにして設定する
+                let value = |desc: &Option<ValueDesc>| desc.as_ref().map(|d| d.build(textures));
+                if let Some(metallic) = value(metallic) {
+                    material = material.with_metallic(metallic?);
+                }
+                if let Some(roughness) = value(roughness) {
+                    material = material.with_roughness(roughness?);
+                }
+                if let Some(specular) = value(specular) {
+                    material = material.with_specular(specular?);
+                }
+                if let Some(sheen) = value(sheen) {
+                    material = material.with_sheen(sheen?);
+                }
+                if let Some(clearcoat) = value(clearcoat) {
+                    material = material.with_clearcoat(clearcoat?);
+                }
+                if let Some(roughness) = value(clearcoat_roughness) {
+                    material = material.with_clearcoat_roughness(roughness?);
+                }
+                if let Some(transmission) = value(transmission) {
+                    material = material.with_transmission(transmission?);
+                }
+                if let Some(ior) = ior {
+                    material = material.with_ior(*ior);
+                }
+                Arc::new(material)
+            }
+        };
+        Ok(material)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AxisDesc {
+    Xy,
+    Xz,
+    Yz,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ShapeDesc {
+    Sphere {
+        center: Point3,
+        radius: f64,
+        material: String,
+    },
+    /// キーフレームに沿って中心が動く球
+    MovingSphere {
+        keyframes: Vec<KeyframeDesc>,
+        radius: f64,
+        material: String,
+    },
+    /// axisで選んだ平面上の長方形。min, maxはその平面の2軸の範囲、kは平面の位置
+    Rect {
+        axis: AxisDesc,
+        min: [f64; 2],
+        max: [f64; 2],
+        k: f64,
+        /// 外向きの法線を負の軸方向にする
+        #[serde(default)]
+        flip: bool,
+        material: String,
+    },
+    Box {
+        min: Point3,
+        max: Point3,
+        material: String,
+    },
+    /// 1枚の三角形。外向きの法線は(v1 - v0) x (v2 - v0)の向きになる
+    Triangle {
+        vertices: [Point3; 3],
+        material: String,
+    },
+    /// OBJファイル。パスはシーンファイルからの相対パス
+    /// materialを指定しなければMTLファイルのマテリアルを使う
+    Mesh {
+        path: PathBuf,
+        material: Option<String>,
+    },
+    /// [objects]で定義した形状を、拡大縮小、回転、平行移動の順に変換して配置する
+    /// 同じ形状を何度配置しても、形状のデータは共有される
+    Instance {
+        object: String,
+        #[serde(default = "default_translate")]
+        translate: Vector3,
+        /// x軸、y軸、z軸の順に回転する角度(度)
+        #[serde(default = "default_rotate")]
+        rotate: Vector3,
+        #[serde(default)]
+        scale: ScaleDesc,
+        /// 変換した後に加える、時刻に応じた移動量
+        #[serde(default)]
+        motion: Vec<OffsetKeyframeDesc>,
+    },
+}
+
+fn default_translate() -> Vector3 {
+    Float3::zero()
+}
+
+fn default_rotate() -> Vector3 {
+    Float3::zero()
+}
+
+/// 拡大率。全ての軸で同じなら数値1つ、軸ごとに変えるなら[x, y, z]で指定する
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(untagged)]
+enum ScaleDesc {
+    Uniform(f64),
+    Axes(Vector3),
+}
+
+impl Default for ScaleDesc {
+    fn default() -> Self {
+        ScaleDesc::Uniform(1.0)
+    }
+}
+
+impl ScaleDesc {
+    fn to_vector(self) -> Vector3 {
+        match self {
+            ScaleDesc::Uniform(s) => Float3::full(s),
+            ScaleDesc::Axes(v) => v,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OffsetKeyframeDesc {
+    time: f64,
+    offset: Vector3,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeDesc {
+    time: f64,
+    center: Point3,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    camera: CameraDesc,
+    #[serde(default)]
+    render: RenderDesc,
+    /// 省略すると黒
+    environment: Option<EnvironmentDesc>,
+    /// マテリアルから名前で参照するテクスチャ
+    #[serde(default)]
+    textures: HashMap<String, TextureDesc>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDesc>,
+    /// instanceから名前で参照する形状。これ自体はシーンに配置されない
+    #[serde(default)]
+    objects: HashMap<String, ShapeDesc>,
+    #[serde(default)]
+    shapes: Vec<ShapeDesc>,
+    #[serde(default)]
+    lights: Vec<LightDesc>,
+}
+
+/// シーンファイルから読み込んだ内容
+pub struct SceneDesc {
+    pub camera: CameraDesc,
+    pub render: RenderDesc,
+    pub shapes: ShapeList,
+    pub environment: Option<Arc<dyn Environment>>,
+    pub lights: Vec<Arc<dyn Light>>,
+}
+
+/// TOML形式のシーンファイルを読み込む
+/// マテリアルは名前を付けて定義し、形状からはその名前で参照する
+/// テクスチャも同じく名前を付けて定義し、マテリアルの色や数値の代わりに名前で参照する
+pub fn load_scene(path: &Path) -> Result<SceneDesc, SceneError> {
+    let source = fs::read_to_string(path).map_err(|source| SceneError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_scene(&source, path)
+}
+
+/// シーンファイルの内容sourceを読み込む。pathはエラーメッセージと相対パスの基準に使う
+fn parse_scene(source: &str, path: &Path) -> Result<SceneDesc, SceneError> {
+    let file: SceneFile = toml::from_str(source).map_err(|source| SceneError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let mut builder = ShapeBuilder {
+        path,
+        base_dir: path.parent().unwrap_or_else(|| Path::new("")),
+        materials: HashMap::new(),
+        objects: HashMap::new(),
+    };
+
+    //名前の順に作ることで、エラーになる場合に毎回同じエラーになるようにする
+    let mut textures: Vec<_> = file.textures.iter().collect();
+    textures.sort_by_key(|(name, _)| name.as_str());
+    let textures: Textures = textures
+        .into_iter()
+        .map(|(name, desc)| {
+            let texture = desc.build(&builder, &format!("textures.{}", name))?;
+            Ok((name.as_str(), texture))
+        })
+        .collect::<Result<_, SceneError>>()?;
+    let mut materials: Vec<_> = file.materials.iter().collect();
+    materials.sort_by_key(|(name, _)| name.as_str());
+    for (name, desc) in materials {
+        let material = desc
+            .build(&textures)
+            .map_err(|message| builder.invalid(&format!("materials.{}", name), &message))?;
+        builder.materials.insert(name.as_str(), material);
+    }
+
+    let mut objects: Vec<_> = file.objects.iter().collect();
+    objects.sort_by_key(|(name, _)| name.as_str());
+    for (name, desc) in objects {
+        let context = format!("objects.{}", name);
+        if let ShapeDesc::Instance { .. } = desc {
+            return Err(builder.invalid(&context, "objects cannot be instances"));
+        }
+        let object = builder.build(desc, &context)?;
+        builder.objects.insert(name.as_str(), Arc::from(object));
+    }
+
+    let mut shapes = ShapeList::new();
+    for (index, desc) in file.shapes.iter().enumerate() {
+        shapes.push(builder.build(desc, &format!("shapes[{}]", index))?);
+    }
+
+    let environment = file
+        .environment
+        .as_ref()
+        .map(|desc| desc.build(builder.base_dir))
+        .transpose()?;
+
+    let lights = file
+        .lights
+        .iter()
+        .enumerate()
+        .map(|(index, desc)| {
+            desc.build()
+                .map_err(|message| builder.invalid(&format!("lights[{}]", index), &message))
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(SceneDesc {
+        camera: file.camera,
+        render: file.render,
+        shapes,
+        environment,
+        lights,
+    })
+}
+
+/// ShapeDescから形状を作る
+struct ShapeBuilder<'a> {
+    path: &'a Path,
+    base_dir: &'a Path,
+    materials: HashMap<&'a str, Arc<dyn Material>>,
+    objects: HashMap<&'a str, Arc<dyn Shape>>,
+}
+
+impl ShapeBuilder<'_> {
+    fn invalid(&self, context: &str, message: &str) -> SceneError {
+        SceneError::Invalid {
+            path: self.path.to_path_buf(),
+            message: format!("{}: {}", context, message),
+        }
+    }
+
+    fn material(&self, name: &str, context: &str) -> Result<Arc<dyn Material>, SceneError> {
+        self.materials
+            .get(name)
+            .map(Arc::clone)
+            .ok_or_else(|| self.invalid(context, &format!("unknown material `{}`", name)))
+    }
+
+    /// contextはエラーメッセージに表示する、シーンファイル内での場所
+    fn build(&self, desc: &ShapeDesc, context: &str) -> Result<Box<dyn Shape>, SceneError> {
+        let shape: Box<dyn Shape> = match desc {
+            ShapeDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => {
+                if *radius <= 0.0 {
+                    return Err(self.invalid(context, "radius must be positive"));
+                }
+                Box::new(Sphere::new(
+                    *center,
+                    *radius,
+                    self.material(material, context)?,
+                ))
+            }
+            ShapeDesc::MovingSphere {
+                keyframes,
+                radius,
+                material,
+            } => {
+                if *radius <= 0.0 {
+                    return Err(self.invalid(context, "radius must be positive"));
+                }
+                if keyframes.is_empty() {
+                    return Err(self.invalid(context, "keyframes must not be empty"));
+                }
+                let keyframes =
+                    Keyframes::new(keyframes.iter().map(|key| (key.time, key.center)).collect());
+                Box::new(MovingSphere::new(
+                    keyframes,
+                    *radius,
+                    self.material(material, context)?,
+                ))
+            }
+            ShapeDesc::Rect {
+                axis,
+                min,
+                max,
+                k,
+                flip,
+                material,
+            } => {
+                let axis = match axis {
+                    AxisDesc::Xy => RectAxisType::XY,
+                    AxisDesc::Xz => RectAxisType::XZ,
+                    AxisDesc::Yz => RectAxisType::YZ,
+                };
+                let rect = Rect::new(
+                    axis,
+                    (min[0], max[0]),
+                    (min[1], max[1]),
+                    *k,
+                    self.material(material, context)?,
+                );
+                Box::new(if *flip { rect.flip_face() } else { rect })
+            }
+            ShapeDesc::Box { min, max, material } => {
+                Box::new(Box3D::new(*min, *max, self.material(material, context)?))
+            }
+            ShapeDesc::Triangle { vertices, material } => {
+                let [p0, p1, p2] = *vertices;
+                if (p1 - p0).cross(p2 - p0).length_squared() == 0.0 {
+                    return Err(self.invalid(context, "triangle vertices must not be collinear"));
+                }
+                Box::new(Triangle::new(p0, p1, p2, self.material(material, context)?))
+            }
+            ShapeDesc::Mesh { path, material } => {
+                let material = match material {
+                    Some(name) => Some(self.material(name, context)?),
+                    None => None,
+                };
+                Box::new(load_obj(&self.base_dir.join(path), material)?)
+            }
+            ShapeDesc::Instance {
+                object,
+                translate,
+                rotate,
+                scale,
+                motion,
+            } => {
+                let shape = self.objects.get(object.as_str()).ok_or_else(|| {
+                    self.invalid(context, &format!("unknown object `{}`", object))
+                })?;
+                let rotation = Quaternion::from_euler(
+                    rotate.x().to_radians(),
+                    rotate.y().to_radians(),
+                    rotate.z().to_radians(),
+                );
+                let matrix = Matrix4::from_trs(*translate, rotation, scale.to_vector());
+                if matrix.inverse().is_none() {
+                    return Err(self.invalid(context, "scale must not be zero"));
+                }
+                let instance = Instance::new(Arc::clone(shape), matrix);
+                if motion.is_empty() {
+                    Box::new(instance)
+                } else {
+                    let motion =
+                        Keyframes::new(motion.iter().map(|key| (key.time, key.offset)).collect());
+                    Box::new(instance.with_motion(motion))
+                }
+            }
+        };
+        Ok(shape)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rayt::ray::Ray;
+
+    const CAMERA: &str = r#"
+[camera]
+lookfrom = [0.0, 0.0, 5.0]
+lookat = [0.0, 0.0, 0.0]
+vfov = 40.0
+
+[materials.white]
+type = "lambertian"
+albedo = [0.73, 0.73, 0.73]
+"#;
+
+    fn parse(shapes: &str) -> Result<SceneDesc, SceneError> {
+        parse_scene(&format!("{}{}", CAMERA, shapes), Path::new("test.toml"))
+    }
+
+    #[test]
+    fn triangle_shape_is_hit() {
+        let scene = parse(
+            r#"
+[[shapes]]
+type = "triangle"
+vertices = [[-1.0, -1.0, 0.0], [1.0, -1.0, 0.0], [0.0, 1.0, 0.0]]
+material = "white"
+"#,
+        )
+        .unwrap();
+        let ray = Ray::new(Float3::new(0.2, 0.0, 5.0), Float3::new(0.0, 0.0, -1.0));
+        let hit = scene.shapes.hit(&ray, 0.001, f64::MAX).unwrap();
+        assert!((hit.t - 5.0).abs() < 1e-9);
+        //外向きの法線は(v1 - v0) x (v2 - v0) = +z
+        assert!(hit.front_face);
+        assert!((hit.n - Float3::zaxis()).length() < 1e-9);
+        //三角形の外側は当たらない
+        let ray = Ray::new(Float3::new(0.9, 0.9, 5.0), Float3::new(0.0, 0.0, -1.0));
+        assert!(scene.shapes.hit(&ray, 0.001, f64::MAX).is_none());
+    }
+
+    #[test]
+    fn degenerate_triangle_is_rejected() {
+        let error = parse(
+            r#"
+[[shapes]]
+type = "triangle"
+vertices = [[0.0, 0.0, 0.0], [1.0, 1.0, 1.0], [2.0, 2.0, 2.0]]
+material = "white"
+"#,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "test.toml: shapes[0]: triangle vertices must not be collinear"
+        );
+    }
+}
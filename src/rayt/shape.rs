@@ -1,31 +1,113 @@
 use std::fmt::Debug;
 use std::sync::Arc;
 
-use super::float3::Float3;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::aabb::Aabb;
+use super::bvh::Bvh;
+use super::environment::Environment;
+use super::float3::{Color, Float3, Point3, Vector3};
+use super::instance::Instance;
+use super::light::Light;
+use super::math::{PI, PI2};
+use super::matrix::Matrix4;
+use super::motion::Keyframes;
+use super::quaternion::Quaternion;
 use super::ray::Ray;
 use super::render::{DiffuseLight, Lambertian, Material, Metal};
 
-use rand::prelude::*;
-
 #[derive(Debug)]
 pub struct HitInfo {
+    /// レイのパラメータ。SimpleScene::traceのレイは方向を正規化しているので原点からの距離になる
     pub t: f64,
     pub p: Float3,
+    /// 法線。常にレイが来た側を向く
     pub n: Float3,
+    /// レイが表面(外向きの法線の側)から当たったかどうか
+    pub front_face: bool,
+    /// テクスチャ座標。形状ごとに0から1の範囲で表面に貼り付ける
+    pub u: f64,
+    pub v: f64,
+    /// テクスチャ座標で測ったレイの太さ。ミップマップのレベルを選ぶのに使う
+    pub footprint: f64,
     pub m: Arc<dyn Material>,
 }
 
 impl HitInfo {
-    fn new(t: f64, p: Float3, n: Float3, m: Arc<dyn Material>) -> Self {
-        Self { t, p, n, m }
+    /// outward_normalは形状の外向きの法線、uvはテクスチャ座標
+    /// uv_densityは表面上の長さ1あたりのテクスチャ座標の変化量(おおよそでよい)
+    /// レイが裏側から当たった場合は反転して、nがレイの来た側を向くようにする
+    pub fn new(
+        t: f64,
+        p: Float3,
+        outward_normal: Float3,
+        uv: (f64, f64),
+        uv_density: f64,
+        ray: &Ray,
+        m: Arc<dyn Material>,
+    ) -> Self {
+        let front_face = ray.direction.dot(outward_normal) < 0.0;
+        let n = if front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+        Self {
+            t,
+            p,
+            n,
+            front_face,
+            u: uv.0,
+            v: uv.1,
+            footprint: ray.width_at(t) * uv_density,
+            m,
+        }
     }
 }
 
-pub trait Shape: Sync + Debug {
+pub trait Shape: Sync + Send + Debug {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitInfo>;
+    /// 形状全体を囲むバウンディングボックス。BVHの構築に使う
+    fn bounding_box(&self) -> Aabb;
+    /// 光を放つ形状のうち、直接サンプリングできるものをlightsに加える
+    fn collect_lights(&self, _lights: &mut Vec<Box<dyn Shape>>) {}
+    /// originからdirectionの方向を選んだときにこの形状に当たる確率密度(立体角あたり)
+    /// random_directionの分布と一致させる
+    fn pdf_value(&self, _origin: Point3, _direction: Vector3) -> f64 {
+        0.0
+    }
+    /// originからこの形状上の点に向かう方向をランダムに選ぶ
+    fn random_direction(&self, _origin: Point3) -> Vector3 {
+        Float3::xaxis()
+    }
 }
 
-#[derive(Debug)]
+/// 光源を直接サンプリングした方向とBSDFでサンプリングした方向を合成するときの重み(パワーヒューリスティック)
+/// Veach "Robust Monte Carlo Methods for Light Transport Simulation" (1997)
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+/// 面積あたりの確率密度を、originから見た立体角あたりの確率密度に変換する
+/// directionはoriginからhitに向かう(正規化していない)レイの方向
+fn area_to_solid_angle(area_pdf: f64, direction: Vector3, hit: &HitInfo) -> f64 {
+    let distance_squared = hit.t * hit.t * direction.length_squared();
+    let cosine = hit.n.dot(direction.normalize()).abs();
+    if cosine > 0.0 {
+        area_pdf * distance_squared / cosine
+    } else {
+        0.0
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Sphere {
     center: Float3,
     radius: f64,
@@ -42,48 +124,155 @@ impl Sphere {
     }
 }
 
+/// 球の外向きの単位法線からテクスチャ座標を求める
+/// uは-x方向から経度を一周し、vは南極(-y)で0、北極(+y)で1になる
+fn sphere_uv(n: Vector3) -> (f64, f64) {
+    let theta = (-n.y()).clamp(-1.0, 1.0).acos();
+    let phi = (-n.z()).atan2(n.x()) + PI;
+    (phi / PI2, theta / PI)
+}
+
+/// 中心がcenter、半径がradiusの球との交差判定
+fn hit_sphere(
+    center: Point3,
+    radius: f64,
+    material: &Arc<dyn Material>,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<HitInfo> {
+    let oc = ray.origin - center;
+    let a = ray.direction.dot(ray.direction);
+    let b = 2.0 * ray.direction.dot(oc);
+    let c = oc.dot(oc) - radius.powi(2);
+    let d = b * b - 4.0 * a * c;
+    if d > 0.0 {
+        let root = d.sqrt();
+        let temp = (-b - root) / (2.0 * a);
+        if t_min < temp && temp < t_max {
+            let p = ray.at(temp);
+            let outward_normal = (p - center) / radius;
+            return Some(HitInfo::new(
+                temp,
+                p,
+                outward_normal,
+                sphere_uv(outward_normal),
+                //vは半周の長さで0から1になる
+                (PI * radius).recip(),
+                ray,
+                Arc::clone(material),
+            ));
+        }
+        let temp = (-b + root) / (2.0 * a);
+        if t_min < temp && temp < t_max {
+            let p = ray.at(temp);
+            let outward_normal = (p - center) / radius;
+            return Some(HitInfo::new(
+                temp,
+                p,
+                outward_normal,
+                sphere_uv(outward_normal),
+                //vは半周の長さで0から1になる
+                (PI * radius).recip(),
+                ray,
+                Arc::clone(material),
+            ));
+        }
+    }
+    None
+}
+
 impl Shape for Sphere {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitInfo> {
-        let oc = ray.origin - self.center;
-        let a = ray.direction.dot(ray.direction);
-        let b = 2.0 * ray.direction.dot(oc);
-        let c = oc.dot(oc) - self.radius.powi(2);
-        let d = b * b - 4.0 * a * c;
-        if d > 0.0 {
-            let root = d.sqrt();
-            let temp = (-b - root) / (2.0 * a);
-            if t_min < temp && temp < t_max {
-                let p = ray.at(temp);
-                return Some(HitInfo::new(
-                    temp,
-                    p,
-                    (p - self.center) / self.radius,
-                    Arc::clone(&self.material),
-                ));
-            }
-            let temp = (-b + root) / (2.0 * a);
-            if t_min < temp && temp < t_max {
-                let p = ray.at(temp);
-                return Some(HitInfo::new(
-                    temp,
-                    p,
-                    (p - self.center) / self.radius,
-                    Arc::clone(&self.material),
-                ));
-            }
+        hit_sphere(self.center, self.radius, &self.material, ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = Float3::full(self.radius.abs());
+        Aabb::new(self.center - r, self.center + r)
+    }
+
+    fn collect_lights(&self, lights: &mut Vec<Box<dyn Shape>>) {
+        if self.material.is_emissive() {
+            lights.push(Box::new(self.clone()));
         }
-        None
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vector3) -> f64 {
+        let Some(hit) = self.hit(&Ray::new(origin, direction), 0.001, f64::MAX) else {
+            return 0.0;
+        };
+        let distance_squared = (self.center - origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared > radius_squared {
+            //外側からは球が見える円錐の中で一様に選ぶ
+            let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+            1.0 / (PI2 * (1.0 - cos_theta_max))
+        } else {
+            //内側からは球面上で一様に選ぶ
+            area_to_solid_angle(1.0 / (4.0 * PI * radius_squared), direction, &hit)
+        }
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vector3 {
+        let direction = self.center - origin;
+        let distance_squared = direction.length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return self.center + Float3::randpm_unit_vector() * self.radius.abs() - origin;
+        }
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let mut rng = rand::thread_rng();
+        let phi = PI2 * rng.gen::<f64>();
+        let z = 1.0 + rng.gen::<f64>() * (cos_theta_max - 1.0);
+        let r = (1.0 - z * z).sqrt();
+        let w = direction.normalize();
+        let (u, v) = w.orthonormal_basis();
+        u * (r * phi.cos()) + v * (r * phi.sin()) + w * z
     }
 }
 
-#[derive(Debug)]
-enum RectAxisType {
+/// 中心がキーフレームに沿って動く球。レイの時刻の位置で交差判定する
+#[derive(Debug, Clone)]
+pub struct MovingSphere {
+    center: Keyframes,
+    radius: f64,
+    material: Arc<dyn Material>,
+}
+
+impl MovingSphere {
+    pub fn new(center: Keyframes, radius: f64, material: Arc<dyn Material>) -> Self {
+        Self {
+            center,
+            radius,
+            material,
+        }
+    }
+}
+
+impl Shape for MovingSphere {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitInfo> {
+        let center = self.center.at(ray.time);
+        hit_sphere(center, self.radius, &self.material, ray, t_min, t_max)
+    }
+
+    /// 動いている間の全ての位置を囲むボックス
+    fn bounding_box(&self) -> Aabb {
+        let r = Float3::full(self.radius.abs());
+        self.center.points().fold(Aabb::empty(), |acc, center| {
+            acc.surrounding(&Aabb::new(center - r, center + r))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RectAxisType {
     XY,
     XZ,
     YZ,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Rect {
     x0: f64,
     x1: f64,
@@ -91,26 +280,79 @@ pub struct Rect {
     y1: f64,
     k: f64,
     axis: RectAxisType,
-    n: Float3,
+    /// trueなら外向きの法線を負の軸方向にする
+    flip: bool,
     material: Arc<dyn Material>,
 }
 
+impl Rect {
+    /// axisで選んだ平面上の長方形。x, yはその平面の2軸(XZならxとz)の範囲、kは平面の位置
+    /// 外向きの法線は平面に垂直な軸の正の方向(XYなら+z、XZなら+y、YZなら+x)になる
+    pub fn new(
+        axis: RectAxisType,
+        (x0, x1): (f64, f64),
+        (y0, y1): (f64, f64),
+        k: f64,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            x0,
+            x1,
+            y0,
+            y1,
+            k,
+            axis,
+            flip: false,
+            material,
+        }
+    }
+
+    /// 外向きの法線を反転する
+    pub fn flip_face(mut self) -> Self {
+        self.flip = !self.flip;
+        self
+    }
+
+    fn outward_normal(&self) -> Float3 {
+        let n = match self.axis {
+            RectAxisType::XY => Float3::zaxis(),
+            RectAxisType::XZ => Float3::yaxis(),
+            RectAxisType::YZ => Float3::xaxis(),
+        };
+        if self.flip {
+            -n
+        } else {
+            n
+        }
+    }
+
+    /// 平面上の座標(x, y)をワールド座標に変換する
+    fn point(&self, x: f64, y: f64) -> Point3 {
+        match self.axis {
+            RectAxisType::XY => Float3::new(x, y, self.k),
+            RectAxisType::XZ => Float3::new(x, self.k, y),
+            RectAxisType::YZ => Float3::new(self.k, x, y),
+        }
+    }
+
+    fn area(&self) -> f64 {
+        ((self.x1 - self.x0) * (self.y1 - self.y0)).abs()
+    }
+}
+
 impl Shape for Rect {
     fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
         let mut origin = ray.origin;
         let mut direction = ray.direction;
-        let mut axis = Float3::zaxis();
         match self.axis {
             RectAxisType::XY => {}
             RectAxisType::XZ => {
                 origin = Float3::new(origin.x(), origin.z(), origin.y());
                 direction = Float3::new(direction.x(), direction.z(), direction.y());
-                axis = Float3::yaxis();
             }
             RectAxisType::YZ => {
                 origin = Float3::new(origin.y(), origin.z(), origin.x());
                 direction = Float3::new(direction.y(), direction.z(), direction.x());
-                axis = Float3::xaxis();
             }
         }
         let t = (self.k - origin.z()) / direction.z();
@@ -122,13 +364,60 @@ impl Shape for Rect {
         if x < self.x0 || x > self.x1 || y < self.y0 || y > self.y1 {
             return None;
         }
+        //長方形の範囲をそのまま0から1に対応させる
+        let uv = (
+            (x - self.x0) / (self.x1 - self.x0),
+            (y - self.y0) / (self.y1 - self.y0),
+        );
         Some(HitInfo::new(
             t,
             ray.at(t),
-            self.n,
+            self.outward_normal(),
+            uv,
+            self.area().sqrt().recip(),
+            ray,
             Arc::clone(&self.material),
         ))
     }
+
+    fn bounding_box(&self) -> Aabb {
+        let (min, max) = match self.axis {
+            RectAxisType::XY => (
+                Float3::new(self.x0, self.y0, self.k),
+                Float3::new(self.x1, self.y1, self.k),
+            ),
+            RectAxisType::XZ => (
+                Float3::new(self.x0, self.k, self.y0),
+                Float3::new(self.x1, self.k, self.y1),
+            ),
+            RectAxisType::YZ => (
+                Float3::new(self.k, self.x0, self.y0),
+                Float3::new(self.k, self.x1, self.y1),
+            ),
+        };
+        //厚みが0だとスラブ法で判定できないので少しだけ広げる
+        Aabb::new(min, max).pad(1e-4)
+    }
+
+    fn collect_lights(&self, lights: &mut Vec<Box<dyn Shape>>) {
+        if self.material.is_emissive() {
+            lights.push(Box::new(self.clone()));
+        }
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vector3) -> f64 {
+        match self.hit(&Ray::new(origin, direction), 0.001, f64::MAX) {
+            Some(hit) => area_to_solid_angle(1.0 / self.area(), direction, &hit),
+            None => 0.0,
+        }
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vector3 {
+        let mut rng = rand::thread_rng();
+        let x = self.x0 + rng.gen::<f64>() * (self.x1 - self.x0);
+        let y = self.y0 + rng.gen::<f64>() * (self.y1 - self.y0);
+        self.point(x, y) - origin
+    }
 }
 
 #[derive(Debug)]
@@ -145,6 +434,10 @@ impl ShapeList {
     pub fn push(&mut self, object: Box<dyn Shape>) {
         self.objects.push(object);
     }
+    /// 含まれている形状からBVHを構築する
+    pub fn into_bvh(self) -> Bvh {
+        Bvh::new(self.objects)
+    }
 }
 
 impl Shape for ShapeList {
@@ -159,14 +452,69 @@ impl Shape for ShapeList {
         }
         hit_info
     }
+
+    fn bounding_box(&self) -> Aabb {
+        self.objects.iter().fold(Aabb::empty(), |acc, object| {
+            acc.surrounding(&object.bounding_box())
+        })
+    }
+
+    fn collect_lights(&self, lights: &mut Vec<Box<dyn Shape>>) {
+        for object in &self.objects {
+            object.collect_lights(lights);
+        }
+    }
+}
+
+/// ワールドの交差判定に使うデータ構造
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Accel {
+    /// 全ての形状を順番に調べる
+    List,
+    /// BVHで調べる形状を絞り込む
+    Bvh,
 }
 
 pub struct SimpleScene {
-    world: ShapeList,
+    world: Box<dyn Shape>,
+    /// 直接サンプリングする光源
+    lights: Vec<Box<dyn Shape>>,
+    /// どの物体にも当たらなかったレイに届く光。Noneなら黒
+    environment: Option<Arc<dyn Environment>>,
+    /// 点光源などの大きさを持たない光源。直接サンプリングでだけ照らす
+    punctual_lights: Vec<Arc<dyn Light>>,
 }
 
 impl SimpleScene {
-    pub fn new() -> Self {
+    pub fn from_shapes(shapes: ShapeList, accel: Accel) -> Self {
+        let mut lights = Vec::new();
+        shapes.collect_lights(&mut lights);
+        let world: Box<dyn Shape> = match accel {
+            Accel::List => Box::new(shapes),
+            Accel::Bvh => Box::new(shapes.into_bvh()),
+        };
+        Self {
+            world,
+            lights,
+            environment: None,
+            punctual_lights: Vec::new(),
+        }
+    }
+
+    /// 環境光を設定する。環境光も光源の1つとして直接サンプリングする
+    pub fn with_environment(mut self, environment: Arc<dyn Environment>) -> Self {
+        self.environment = Some(environment);
+        self
+    }
+
+    /// 大きさを持たない光源を加える
+    pub fn with_light(mut self, light: Arc<dyn Light>) -> Self {
+        self.punctual_lights.push(light);
+        self
+    }
+
+    /// コーネルボックス
+    pub fn cornell_box() -> ShapeList {
         let mut world = ShapeList::new();
         // world.push(Box::new(Sphere::new(
         //     Float3::new(0.0, 0.0, 1.0),
@@ -198,10 +546,33 @@ impl SimpleScene {
         //     )));
         // }
 
-        world.push(Box::new(Box3D::new(
-            Float3::new(130.0, 0.0, 65.0),
-            Float3::new(295.0, 165.0, 230.0),
-            Arc::new(Lambertian::new(Float3::new(0.73, 0.73, 0.73))),
+        //原点に置いた箱を回転して配置する
+        let white: Arc<dyn Material> = Arc::new(Lambertian::new(Float3::new(0.73, 0.73, 0.73)));
+        let short_box: Arc<dyn Shape> = Arc::new(Box3D::new(
+            Float3::zero(),
+            Float3::new(165.0, 165.0, 165.0),
+            Arc::clone(&white),
+        ));
+        world.push(Box::new(Instance::new(
+            short_box,
+            Matrix4::translation(Float3::new(130.0, 0.0, 65.0))
+                * Matrix4::rotation(Quaternion::from_axis_angle(
+                    Float3::yaxis(),
+                    (-18.0f64).to_radians(),
+                )),
+        )));
+        let tall_box: Arc<dyn Shape> = Arc::new(Box3D::new(
+            Float3::zero(),
+            Float3::new(165.0, 330.0, 165.0),
+            white,
+        ));
+        world.push(Box::new(Instance::new(
+            tall_box,
+            Matrix4::translation(Float3::new(265.0, 0.0, 295.0))
+                * Matrix4::rotation(Quaternion::from_axis_angle(
+                    Float3::yaxis(),
+                    15.0f64.to_radians(),
+                )),
         )));
 
         //コーネルボックスを作成
@@ -213,7 +584,7 @@ impl SimpleScene {
             y1: 555.0,
             k: 555.0,
             axis: RectAxisType::YZ,
-            n: Float3::new(-1.0, 0.0, 0.0),
+            flip: true,
             material: Arc::new(Lambertian::new(Float3::new(0.12, 0.45, 0.15))),
         }));
         //右の壁
@@ -224,7 +595,7 @@ impl SimpleScene {
             y1: 555.0,
             k: 0.0,
             axis: RectAxisType::YZ,
-            n: Float3::new(1.0, 0.0, 0.0),
+            flip: false,
             material: Arc::new(Lambertian::new(Float3::new(0.65, 0.05, 0.05))),
         }));
         //天井のライト
@@ -235,8 +606,8 @@ impl SimpleScene {
             y1: 332.0,
             k: 554.0,
             axis: RectAxisType::XZ,
-            n: Float3::new(0.0, -1.0, 0.0),
-            material: Arc::new(DiffuseLight::new(Float3::new(40.0, 40.0, 40.0))),
+            flip: true,
+            material: Arc::new(DiffuseLight::one_sided(Float3::new(40.0, 40.0, 40.0))),
         }));
         //天井
         world.push(Box::new(Rect {
@@ -246,7 +617,7 @@ impl SimpleScene {
             y1: 555.0,
             k: 555.0,
             axis: RectAxisType::XZ,
-            n: Float3::new(0.0, -1.0, 0.0),
+            flip: true,
             material: Arc::new(Lambertian::new(Float3::new(0.73, 0.73, 0.73))),
         }));
         //奥の壁
@@ -257,7 +628,7 @@ impl SimpleScene {
             y1: 555.0,
             k: 555.0,
             axis: RectAxisType::XY,
-            n: Float3::new(0.0, 0.0, -1.0),
+            flip: true,
             material: Arc::new(Lambertian::new(Float3::new(0.73, 0.73, 0.73))),
         }));
         //床
@@ -268,30 +639,230 @@ impl SimpleScene {
             y1: 555.0,
             k: 0.0,
             axis: RectAxisType::XZ,
-            n: Float3::new(0.0, 0.0, 1.0),
+            flip: false,
             material: Arc::new(Lambertian::new(Float3::new(0.73, 0.73, 0.73))),
         }));
 
-        Self { world }
+        world
     }
 
+    /// 地面の大きな球の上に、ランダムな色の球と光源の球を並べたシーン
+    pub fn spheres() -> ShapeList {
+        //毎回同じ画像になるように、色は固定のシードから決める
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut world = ShapeList::new();
+        //地面
+        world.push(Box::new(Sphere::new(
+            Float3::new(0.0, -100.5, 1.0),
+            100.0,
+            Arc::new(Lambertian::new(Float3::new(0.5, 0.5, 0.5))),
+        )));
+        world.push(Box::new(Sphere::new(
+            Float3::new(0.0, 0.0, 1.0),
+            0.5,
+            Arc::new(Lambertian::new(Float3::new(0.8, 0.3, 0.3))),
+        )));
+        world.push(Box::new(Sphere::new(
+            Float3::new(1.1, 0.0, 1.0),
+            0.5,
+            Arc::new(Metal::new(Float3::new(0.8, 0.8, 0.8), 0.3)),
+        )));
+        for i in 0..5 {
+            world.push(Box::new(Sphere::new(
+                Float3::new(i as f64 * 0.6 - 1.2, -0.35, 0.3),
+                0.15,
+                Arc::new(Lambertian::new(Float3::new(
+                    rng.gen(),
+                    rng.gen(),
+                    rng.gen(),
+                ))),
+            )));
+        }
+        //光源
+        world.push(Box::new(Sphere::new(
+            Float3::new(0.0, 3.0, 1.0),
+            1.0,
+            Arc::new(DiffuseLight::new(Float3::new(8.0, 8.0, 8.0))),
+        )));
+
+        world
+    }
+
+    /// 地面の上に小さな球をgrid x gridの格子状に並べたシーン。BVHの性能計測用
+    pub fn sphere_grid(grid: usize) -> ShapeList {
+        //計測のたびに同じシーンになるように、位置のずれと色は固定のシードから決める
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut world = ShapeList::new();
+        //地面
+        world.push(Box::new(Sphere::new(
+            Float3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Arc::new(Lambertian::new(Float3::new(0.5, 0.5, 0.5))),
+        )));
+        let half = grid as f64 * 0.5;
+        for i in 0..grid {
+            for j in 0..grid {
+                let center = Float3::new(
+                    i as f64 - half + 0.5 + rng.gen_range(-0.3..0.3),
+                    0.2,
+                    j as f64 - half + 0.5 + rng.gen_range(-0.3..0.3),
+                );
+                world.push(Box::new(Sphere::new(
+                    center,
+                    0.2,
+                    Arc::new(Lambertian::new(Float3::new(
+                        rng.gen(),
+                        rng.gen(),
+                        rng.gen(),
+                    ))),
+                )));
+            }
+        }
+        //光源
+        world.push(Box::new(Sphere::new(
+            Float3::new(0.0, half + 60.0, half + 20.0),
+            half,
+            Arc::new(DiffuseLight::new(Float3::new(6.0, 6.0, 6.0))),
+        )));
+
+        world
+    }
+
+    /// レイの方向から届く光を求める
+    /// BSDFに鏡面でないローブがあれば光源を直接サンプリングし(Next Event Estimation)、
+    /// BSDFのサンプリングで光源に当たった場合とMIS(Multiple Importance Sampling)で合成する
     pub fn trace(&self, ray: Ray, depth: usize) -> Float3 {
-        let hit_info = self.world.hit(&ray, 0.001, f64::MAX);
-        if let Some(hit) = hit_info {
-            let emitted = hit.m.emitted(&ray, &hit);
-            let scatter_info = if depth > 0 {
-                hit.m.scatter(&ray, &hit)
-            } else {
-                None
+        let mut ray = Ray {
+            direction: ray.direction.normalize(),
+            ..ray
+        };
+        let mut radiance = Float3::zero();
+        let mut throughput = Float3::one();
+        //直前の散乱方向を選んだ確率密度。カメラからのレイと鏡面反射ではNone
+        let mut bsdf_pdf: Option<f64> = None;
+        for bounce in 0..=depth {
+            //どの物体にも当たらなければ環境光が届く
+            let hit = self.world.hit(&ray, 0.001, f64::MAX);
+            let emitted = match &hit {
+                Some(hit) => hit.m.emitted(&ray, hit),
+                None => self.background(ray.direction),
+            };
+            let weight = match bsdf_pdf {
+                Some(pdf) => power_heuristic(pdf, self.light_pdf(ray.origin, ray.direction)),
+                None => 1.0,
+            };
+            radiance += throughput * emitted * weight;
+            let Some(hit) = hit else {
+                break;
             };
-            if let Some(scatter) = scatter_info {
-                return self.trace(scatter.ray, depth - 1) * scatter.albedo + emitted;
-            } else {
-                return emitted;
+            if bounce == depth {
+                break;
             }
-        } else {
-            Float3::new(0.0, 0.0, 0.0)
+            let wo = -ray.direction;
+            //鏡面のローブだけのマテリアルではevalとpdfが0なので、直接光は0になる
+            //鏡面と拡散のローブを持つマテリアルでは、どちらのローブを選ぶかに関係なく直接光を求める
+            radiance += throughput
+                * (self.sample_light(wo, &hit, ray.time)
+                    + self.sample_punctual_lights(wo, &hit, ray.time));
+            let Some(sample) = hit.m.sample(wo, &hit) else {
+                break;
+            };
+            bsdf_pdf = (!sample.specular).then_some(sample.pdf);
+            throughput = throughput * sample.weight;
+            //太さの広がりは変えずに、当たった点での太さから続ける
+            ray = Ray::with_time(hit.p, sample.wi, ray.time)
+                .with_cone(ray.width_at(hit.t), ray.spread);
+        }
+        radiance
+    }
+
+    /// 全ての大きさを持たない光源からシャドウレイを飛ばし、hitの点からwoの方向に届く直接光を求める
+    /// BSDFのサンプリングでは当たらないのでMISは使わない
+    fn sample_punctual_lights(&self, wo: Vector3, hit: &HitInfo, ray_time: f64) -> Color {
+        let mut sum = Float3::zero();
+        for light in &self.punctual_lights {
+            let Some(sample) = light.sample(hit.p) else {
+                continue;
+            };
+            let f = hit.m.eval(sample.wi, wo, hit);
+            if f.length_squared() <= 0.0 {
+                continue;
+            }
+            let shadow_ray = Ray::with_time(hit.p, sample.wi, ray_time);
+            if self
+                .world
+                .hit(&shadow_ray, 0.001, sample.distance * (1.0 - 1e-6))
+                .is_some()
+            {
+                continue;
+            }
+            sum += f * sample.irradiance * sample.wi.dot(hit.n).abs();
+        }
+        sum
+    }
+
+    /// directionの方向の環境光
+    fn background(&self, direction: Vector3) -> Color {
+        match &self.environment {
+            Some(environment) => environment.radiance(direction),
+            None => Float3::zero(),
+        }
+    }
+
+    /// 直接サンプリングする光源の数。環境光があればそれも1つと数える
+    fn light_count(&self) -> usize {
+        self.lights.len() + usize::from(self.environment.is_some())
+    }
+
+    /// 全ての光源からoriginを見たときの、directionの方向の確率密度
+    /// 光源は一様に選ぶので、各光源の確率密度の平均になる
+    fn light_pdf(&self, origin: Point3, direction: Vector3) -> f64 {
+        if self.light_count() == 0 {
+            return 0.0;
+        }
+        let sum: f64 = self
+            .lights
+            .iter()
+            .map(|light| light.pdf_value(origin, direction))
+            .sum();
+        let environment = self
+            .environment
+            .as_ref()
+            .map_or(0.0, |environment| environment.pdf(direction));
+        (sum + environment) / self.light_count() as f64
+    }
+
+    /// 光源を1つ選んでその上の点に向けてシャドウレイを飛ばし、hitの点からwoの方向に届く直接光を求める
+    fn sample_light(&self, wo: Vector3, hit: &HitInfo, ray_time: f64) -> Float3 {
+        if self.light_count() == 0 {
+            return Float3::zero();
         }
+        let index = rand::thread_rng().gen_range(0..self.light_count());
+        let wi = match (self.lights.get(index), &self.environment) {
+            (Some(light), _) => light.random_direction(hit.p).normalize(),
+            (None, Some(environment)) => environment.sample().0,
+            (None, None) => unreachable!(),
+        };
+        //法線の裏側の方向は透過するマテリアルだけが選ぶので、それ以外ではpdfが0になる
+        let cos_i = wi.dot(hit.n).abs();
+        let bsdf_pdf = hit.m.pdf(wi, wo, hit);
+        if cos_i <= 0.0 || bsdf_pdf <= 0.0 {
+            return Float3::zero();
+        }
+        let light_pdf = self.light_pdf(hit.p, wi);
+        if light_pdf <= 0.0 {
+            return Float3::zero();
+        }
+        //シャドウレイが最初に当たった物体の放射を使う。光源が遮られていれば0になる
+        //何にも当たらなければ環境光が届く
+        let shadow_ray = Ray::with_time(hit.p, wi, ray_time);
+        let emitted = match self.world.hit(&shadow_ray, 0.001, f64::MAX) {
+            Some(light_hit) => light_hit.m.emitted(&shadow_ray, &light_hit),
+            None => self.background(wi),
+        };
+        emitted
+            * hit.m.eval(wi, wo, hit)
+            * (cos_i / light_pdf * power_heuristic(light_pdf, bsdf_pdf))
     }
 }
 
@@ -302,74 +873,42 @@ pub struct Box3D {
     shapes: ShapeList,
 }
 impl Box3D {
-    fn new(p0: Float3, p1: Float3, material: Arc<dyn Material>) -> Self {
+    pub fn new(p0: Float3, p1: Float3, material: Arc<dyn Material>) -> Self {
         let mut shapes = ShapeList::new();
-        shapes.push(Box::new(Rect {
-            x0: p0.x(),
-            x1: p1.x(),
-            y0: p0.y(),
-            y1: p1.y(),
-            k: p1.z(),
-            axis: RectAxisType::XY,
-            n: Float3::new(1.0, 0.0, 0.0),
-            material: Arc::clone(&material),
-        }));
-        shapes.push(Box::new(Rect {
-            x0: p0.x(),
-            x1: p1.x(),
-            y0: p0.y(),
-            y1: p1.y(),
-            k: p0.z(),
-            axis: RectAxisType::XY,
-            n: Float3::new(-1.0, 0.0, 0.0),
-            material: Arc::clone(&material),
-        }));
-        shapes.push(Box::new(Rect {
-            x0: p0.x(),
-            x1: p1.x(),
-            y0: p0.z(),
-            y1: p1.z(),
-            k: p1.y(),
-            axis: RectAxisType::XZ,
-            n: Float3::new(0.0, 1.0, 0.0),
-            material: Arc::clone(&material),
-        }));
-        shapes.push(Box::new(Rect {
-            x0: p0.x(),
-            x1: p1.x(),
-            y0: p0.z(),
-            y1: p1.z(),
-            k: p0.y(),
-            axis: RectAxisType::XZ,
-            n: Float3::new(0.0, -1.0, 0.0),
-            material: Arc::clone(&material),
-        }));
-        // shapes.push(
-        //     ShapeBuilder::new()
-        //         .material(Arc::clone(&material))
-        //         .rect_xz(p0.x(), p1.x(), p0.z(), p1.z(), p1.y())
-        //         .build(),
-        // );
-        // shapes.push(
-        //     ShapeBuilder::new()
-        //         .material(Arc::clone(&material))
-        //         .rect_xz(p0.x(), p1.x(), p0.z(), p1.z(), p0.y())
-        //         .flip_face()
-        //         .build(),
-        // );
-        // shapes.push(
-        //     ShapeBuilder::new()
-        //         .material(Arc::clone(&material))
-        //         .rect_yz(p0.y(), p1.y(), p0.z(), p1.z(), p1.x())
-        //         .build(),
-        // );
-        // shapes.push(
-        //     ShapeBuilder::new()
-        //         .material(Arc::clone(&material))
-        //         .rect_yz(p0.y(), p1.y(), p0.z(), p1.z(), p0.x())
-        //         .flip_face()
-        //         .build(),
-        // );
+        //各面の外向きの法線が箱の外側を向くように、p0側の面は反転する
+        let xy = |k| {
+            Rect::new(
+                RectAxisType::XY,
+                (p0.x(), p1.x()),
+                (p0.y(), p1.y()),
+                k,
+                Arc::clone(&material),
+            )
+        };
+        shapes.push(Box::new(xy(p1.z())));
+        shapes.push(Box::new(xy(p0.z()).flip_face()));
+        let xz = |k| {
+            Rect::new(
+                RectAxisType::XZ,
+                (p0.x(), p1.x()),
+                (p0.z(), p1.z()),
+                k,
+                Arc::clone(&material),
+            )
+        };
+        shapes.push(Box::new(xz(p1.y())));
+        shapes.push(Box::new(xz(p0.y()).flip_face()));
+        let yz = |k| {
+            Rect::new(
+                RectAxisType::YZ,
+                (p0.y(), p1.y()),
+                (p0.z(), p1.z()),
+                k,
+                Arc::clone(&material),
+            )
+        };
+        shapes.push(Box::new(yz(p1.x())));
+        shapes.push(Box::new(yz(p0.x()).flip_face()));
         Self { p0, p1, shapes }
     }
 }
@@ -377,4 +916,12 @@ impl Shape for Box3D {
     fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
         self.shapes.hit(ray, t0, t1)
     }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::from_points(self.p0, self.p1)
+    }
+
+    fn collect_lights(&self, lights: &mut Vec<Box<dyn Shape>>) {
+        self.shapes.collect_lights(lights);
+    }
 }
@@ -0,0 +1,205 @@
+use rand::Rng;
+
+use super::environment::Environment;
+use super::float3::{Color, Float3, Vector3};
+use super::math::{PI, PI2};
+
+/// 太陽の視半径(ラジアン)
+const SUN_ANGULAR_RADIUS: f64 = 0.00465;
+/// 大気の外での太陽の輝度(kcd/m^2)
+const SUN_LUMINANCE: f64 = 2.0e6;
+/// 輝度(kcd/m^2)をレンダリングの値に直す倍率。晴れた空の天頂がおよそ0.2から0.5になる
+const LUMINANCE_SCALE: f64 = 0.04;
+/// 直接サンプリングで太陽を選ぶ確率。残りは空の半球から一様に選ぶ
+const SUN_PROBABILITY: f64 = 0.5;
+
+/// Preethamの解析的な昼間の空のモデルと、それに合わせて減衰させた太陽
+/// Preetham et al. "A Practical Analytic Model for Daylight" (1999)
+/// 地平線より下は地面に遮られているものとして黒にする
+#[derive(Debug, Clone)]
+pub struct PreethamSky {
+    /// 太陽の方向(正規化済み)
+    sun: Vector3,
+    /// 天頂での輝度Y(kcd/m^2)と色度x, y
+    zenith: [f64; 3],
+    /// Y, x, yそれぞれのPerez関数の係数A, B, C, D, E
+    perez: [[f64; 5]; 3],
+    /// 太陽の天頂角θsでのPerez関数の値。天頂の値で正規化するのに使う
+    perez_zenith: [f64; 3],
+    /// 大気で減衰した太陽の放射輝度(レンダリングの値)
+    sun_radiance: Color,
+    cos_sun_radius: f64,
+    intensity: f64,
+}
+
+impl PreethamSky {
+    /// elevationは太陽の高度、azimuthは方位角(度)。方位角は0で+z、90で-xの方向になる
+    /// turbidityは大気の濁り具合で、2で非常に澄んだ空、3で晴れ、6以上で霞んだ空になる
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64) -> Self {
+        let t = turbidity.clamp(1.7, 10.0);
+        let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+        let sun = Float3::new(
+            -azimuth.sin() * elevation.cos(),
+            elevation.sin(),
+            azimuth.cos() * elevation.cos(),
+        );
+        //地平線より下の太陽には対応していないので、空の色は地平線にあるものとして求める
+        let theta_s = (PI * 0.5 - elevation).clamp(0.0, PI * 0.5);
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic =
+            |c: [f64; 4]| c[0] * theta_s.powi(3) + c[1] * theta_s.powi(2) + c[2] * theta_s + c[3];
+        let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_yc = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+        let perez_zenith = perez.map(|c| perez_function(c, 1.0, theta_s));
+
+        Self {
+            sun,
+            zenith: [zenith_y, zenith_x, zenith_yc],
+            perez,
+            perez_zenith,
+            sun_radiance: sun_radiance(theta_s, t),
+            cos_sun_radius: SUN_ANGULAR_RADIUS.cos(),
+            intensity: 1.0,
+        }
+    }
+
+    /// 明るさを何倍にするか
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// 太陽を除いた空の放射輝度。directionは地平線より上の方向
+    fn sky(&self, direction: Vector3) -> Color {
+        //地平線の近くでPerez関数が発散しないように、天頂角の余弦を少し持ち上げる
+        let cos_theta = direction.y().max(0.01);
+        let gamma = direction.dot(self.sun).clamp(-1.0, 1.0).acos();
+        let [y, x, yc] = [0, 1, 2].map(|i| {
+            self.zenith[i] * perez_function(self.perez[i], cos_theta, gamma) / self.perez_zenith[i]
+        });
+        xyy_to_rgb(x, yc, y) * LUMINANCE_SCALE
+    }
+
+    fn sun_visible(&self) -> bool {
+        self.sun.y() > -SUN_ANGULAR_RADIUS
+    }
+}
+
+impl Environment for PreethamSky {
+    fn radiance(&self, direction: Vector3) -> Color {
+        if direction.y() <= 0.0 {
+            return Float3::zero();
+        }
+        let mut radiance = self.sky(direction);
+        if direction.dot(self.sun) >= self.cos_sun_radius {
+            radiance += self.sun_radiance;
+        }
+        radiance * self.intensity
+    }
+
+    fn sample(&self) -> (Vector3, f64) {
+        let mut rng = rand::thread_rng();
+        let direction = if self.sun_visible() && rng.gen::<f64>() < SUN_PROBABILITY {
+            //太陽の円錐の中から一様に選ぶ
+            let cos = 1.0 - rng.gen::<f64>() * (1.0 - self.cos_sun_radius);
+            let sin = (1.0 - cos * cos).max(0.0).sqrt();
+            let phi = PI2 * rng.gen::<f64>();
+            let (u, v) = self.sun.orthonormal_basis();
+            (u * (sin * phi.cos()) + v * (sin * phi.sin()) + self.sun * cos).normalize()
+        } else {
+            //上の半球から一様に選ぶ
+            let y: f64 = rng.gen();
+            let r = (1.0 - y * y).max(0.0).sqrt();
+            let phi = PI2 * rng.gen::<f64>();
+            Float3::new(r * phi.cos(), y, r * phi.sin())
+        };
+        (direction, self.pdf(direction))
+    }
+
+    fn pdf(&self, direction: Vector3) -> f64 {
+        let sun_probability = if self.sun_visible() {
+            SUN_PROBABILITY
+        } else {
+            0.0
+        };
+        let mut pdf = 0.0;
+        if direction.y() > 0.0 {
+            pdf += (1.0 - sun_probability) / PI2;
+        }
+        if direction.dot(self.sun) >= self.cos_sun_radius {
+            pdf += sun_probability / (PI2 * (1.0 - self.cos_sun_radius));
+        }
+        pdf
+    }
+}
+
+/// Perezの空の輝度分布の関数。cos_thetaは天頂角の余弦、gammaは太陽となす角
+fn perez_function(c: [f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    let [a, b, c, d, e] = c;
+    let cos_gamma = gamma.cos();
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+/// 色度x, yと輝度YをリニアなsRGBにする
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    if y <= 0.0 {
+        return Float3::zero();
+    }
+    let cx = x * luminance / y;
+    let cz = (1.0 - x - y) * luminance / y;
+    let cy = luminance;
+    Float3::new(
+        (3.2406 * cx - 1.5372 * cy - 0.4986 * cz).max(0.0),
+        (-0.9689 * cx + 1.8758 * cy + 0.0415 * cz).max(0.0),
+        (0.0557 * cx - 0.2040 * cy + 1.0570 * cz).max(0.0),
+    )
+}
+
+/// 天頂角theta_sの太陽が大気を通ったあとの放射輝度
+/// RGBをそれぞれ代表的な波長で扱い、Rayleigh散乱とエアロゾルによる減衰だけを考える
+fn sun_radiance(theta_s: f64, turbidity: f64) -> Color {
+    //大気を通る距離(天頂方向を1とする)。地平線の近くでも有限になる近似式
+    let mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let transmittance = |lambda: f64| {
+        let rayleigh = (-0.008735 * lambda.powf(-4.08) * mass).exp();
+        let aerosol = (-beta * lambda.powf(-1.3) * mass).exp();
+        rayleigh * aerosol
+    };
+    //赤、緑、青の代表的な波長(μm)
+    Float3::new(
+        transmittance(0.65),
+        transmittance(0.57),
+        transmittance(0.475),
+    ) * (SUN_LUMINANCE * LUMINANCE_SCALE)
+}
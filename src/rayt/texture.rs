@@ -0,0 +1,348 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use super::float3::{Color, Float3, Point3};
+use super::math::PI2;
+use super::noise::{Perlin, Worley};
+use super::shape::HitInfo;
+
+/// 表面の位置によって変わる値。マテリアルの色や粗さに使う
+/// u, vはHitInfoのテクスチャ座標、pはワールド座標での位置
+pub trait Texture: Sync + Send + Debug {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color;
+
+    /// テクスチャ座標で測った太さがfootprintのレイが当たったときの値
+    /// 画像のようにフィルタリングするテクスチャはこちらも実装する
+    fn filtered(&self, u: f64, v: f64, p: Point3, _footprint: f64) -> Color {
+        self.value(u, v, p)
+    }
+
+    /// hitの点での値
+    fn value_at(&self, hit: &HitInfo) -> Color {
+        self.filtered(hit.u, hit.v, hit.p, hit.footprint)
+    }
+
+    /// 粗さなどの数値として使う場合の、hitの点での値。RGBの平均
+    fn scalar_at(&self, hit: &HitInfo) -> f64 {
+        let c = self.value_at(hit);
+        (c.x() + c.y() + c.z()) / 3.0
+    }
+}
+
+/// どこでも同じ色
+#[derive(Debug, Clone)]
+pub struct SolidColor {
+    color: Color,
+}
+
+impl SolidColor {
+    pub fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: Point3) -> Color {
+        self.color
+    }
+}
+
+/// 空間を一辺sizeの立方体に区切った市松模様
+/// テクスチャ座標を使わないので、どんな形状にも継ぎ目なく貼れる
+#[derive(Debug)]
+pub struct Checker {
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+    size: f64,
+}
+
+impl Checker {
+    pub fn new(even: Arc<dyn Texture>, odd: Arc<dyn Texture>, size: f64) -> Self {
+        Self { even, odd, size }
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.filtered(u, v, p, 0.0)
+    }
+
+    fn filtered(&self, u: f64, v: f64, p: Point3, footprint: f64) -> Color {
+        let cell = |x: f64| (x / self.size).floor() as i64;
+        if (cell(p.x()) + cell(p.y()) + cell(p.z())).rem_euclid(2) == 0 {
+            self.even.filtered(u, v, p, footprint)
+        } else {
+            self.odd.filtered(u, v, p, footprint)
+        }
+    }
+}
+
+/// テクスチャ座標をu方向にwidth個、v方向にheight個に区切った市松模様
+#[derive(Debug)]
+pub struct UvChecker {
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+    width: u32,
+    height: u32,
+}
+
+impl UvChecker {
+    pub fn new(even: Arc<dyn Texture>, odd: Arc<dyn Texture>, width: u32, height: u32) -> Self {
+        Self {
+            even,
+            odd,
+            width,
+            height,
+        }
+    }
+}
+
+impl Texture for UvChecker {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.filtered(u, v, p, 0.0)
+    }
+
+    fn filtered(&self, u: f64, v: f64, p: Point3, footprint: f64) -> Color {
+        let x = (u * self.width as f64).floor() as i64;
+        let y = (v * self.height as f64).floor() as i64;
+        if (x + y).rem_euclid(2) == 0 {
+            self.even.filtered(u, v, p, footprint)
+        } else {
+            self.odd.filtered(u, v, p, footprint)
+        }
+    }
+}
+
+/// テクスチャ座標(u, v)を赤と緑で表す。UVの確認用
+#[derive(Debug, Clone)]
+pub struct UvColor;
+
+impl Texture for UvColor {
+    fn value(&self, u: f64, v: f64, _p: Point3) -> Color {
+        Float3::new(u.rem_euclid(1.0), v.rem_euclid(1.0), 0.0)
+    }
+}
+
+/// t = 0でlow、t = 1でhighになるように線形補間する
+fn mix(low: Color, high: Color, t: f64) -> Color {
+    low * (1.0 - t) + high * t
+}
+
+/// Perlinノイズ。octavesが2以上ならfBmになる
+/// ノイズの値を0から1に直して、lowからhighの間の色にする
+#[derive(Debug, Clone)]
+pub struct NoiseTexture {
+    perlin: Perlin,
+    /// ノイズの周波数。大きいほど模様が細かくなる
+    scale: f64,
+    octaves: u32,
+    low: Color,
+    high: Color,
+}
+
+impl NoiseTexture {
+    pub fn new(perlin: Perlin, scale: f64) -> Self {
+        Self {
+            perlin,
+            scale,
+            octaves: 1,
+            low: Float3::zero(),
+            high: Float3::one(),
+        }
+    }
+
+    pub fn with_octaves(mut self, octaves: u32) -> Self {
+        self.octaves = octaves;
+        self
+    }
+
+    pub fn with_colors(mut self, low: Color, high: Color) -> Self {
+        self.low = low;
+        self.high = high;
+        self
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let n = self.perlin.fbm(p * self.scale, self.octaves);
+        mix(self.low, self.high, (0.5 * (1.0 + n)).clamp(0.0, 1.0))
+    }
+}
+
+/// 乱流。ノイズの絶対値を重ねるので、谷の部分が細い筋になる
+#[derive(Debug, Clone)]
+pub struct Turbulence {
+    perlin: Perlin,
+    scale: f64,
+    octaves: u32,
+    low: Color,
+    high: Color,
+}
+
+impl Turbulence {
+    pub fn new(perlin: Perlin, scale: f64) -> Self {
+        Self {
+            perlin,
+            scale,
+            octaves: 7,
+            low: Float3::zero(),
+            high: Float3::one(),
+        }
+    }
+
+    pub fn with_octaves(mut self, octaves: u32) -> Self {
+        self.octaves = octaves;
+        self
+    }
+
+    pub fn with_colors(mut self, low: Color, high: Color) -> Self {
+        self.low = low;
+        self.high = high;
+        self
+    }
+}
+
+impl Texture for Turbulence {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let t = self.perlin.turbulence(p * self.scale, self.octaves);
+        mix(self.low, self.high, t.clamp(0.0, 1.0))
+    }
+}
+
+/// 大理石。x軸に沿った縞を乱流で歪ませる
+#[derive(Debug, Clone)]
+pub struct Marble {
+    perlin: Perlin,
+    scale: f64,
+    octaves: u32,
+    /// 縞を歪ませる強さ
+    strength: f64,
+    base: Color,
+    vein: Color,
+}
+
+impl Marble {
+    pub fn new(perlin: Perlin, scale: f64) -> Self {
+        Self {
+            perlin,
+            scale,
+            octaves: 7,
+            strength: 10.0,
+            base: Float3::new(0.9, 0.9, 0.88),
+            vein: Float3::new(0.2, 0.2, 0.25),
+        }
+    }
+
+    pub fn with_octaves(mut self, octaves: u32) -> Self {
+        self.octaves = octaves;
+        self
+    }
+
+    pub fn with_strength(mut self, strength: f64) -> Self {
+        self.strength = strength;
+        self
+    }
+
+    pub fn with_colors(mut self, base: Color, vein: Color) -> Self {
+        self.base = base;
+        self.vein = vein;
+        self
+    }
+}
+
+impl Texture for Marble {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let p = p * self.scale;
+        let phase = p.x() + self.strength * self.perlin.turbulence(p, self.octaves);
+        //sinが1に近い細い部分を筋にする
+        let t = (0.5 * (1.0 + phase.sin())).powi(4);
+        mix(self.base, self.vein, t)
+    }
+}
+
+/// 木目。y軸を中心とする年輪をノイズで歪ませる
+#[derive(Debug, Clone)]
+pub struct Wood {
+    perlin: Perlin,
+    /// 距離1あたりの年輪の数
+    scale: f64,
+    octaves: u32,
+    /// 年輪を歪ませる強さ(年輪の間隔に対する割合)
+    strength: f64,
+    light: Color,
+    dark: Color,
+}
+
+impl Wood {
+    pub fn new(perlin: Perlin, scale: f64) -> Self {
+        Self {
+            perlin,
+            scale,
+            octaves: 3,
+            strength: 0.5,
+            light: Float3::new(0.75, 0.55, 0.33),
+            dark: Float3::new(0.45, 0.27, 0.12),
+        }
+    }
+
+    pub fn with_octaves(mut self, octaves: u32) -> Self {
+        self.octaves = octaves;
+        self
+    }
+
+    pub fn with_strength(mut self, strength: f64) -> Self {
+        self.strength = strength;
+        self
+    }
+
+    pub fn with_colors(mut self, light: Color, dark: Color) -> Self {
+        self.light = light;
+        self.dark = dark;
+        self
+    }
+}
+
+impl Texture for Wood {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let p = p * self.scale;
+        let radius = (p.x() * p.x() + p.z() * p.z()).sqrt();
+        let rings = radius + self.strength * self.perlin.fbm(p, self.octaves);
+        //年輪の境目で色が急に変わらないように、cosで滑らかに繰り返す
+        let t = 0.5 - 0.5 * (rings * PI2).cos();
+        mix(self.light, self.dark, t)
+    }
+}
+
+/// Worleyノイズ。特徴点の近くがlow、遠くがhighになる細胞状の模様
+#[derive(Debug, Clone)]
+pub struct WorleyTexture {
+    worley: Worley,
+    scale: f64,
+    low: Color,
+    high: Color,
+}
+
+impl WorleyTexture {
+    pub fn new(worley: Worley, scale: f64) -> Self {
+        Self {
+            worley,
+            scale,
+            low: Float3::zero(),
+            high: Float3::one(),
+        }
+    }
+
+    pub fn with_colors(mut self, low: Color, high: Color) -> Self {
+        self.low = low;
+        self.high = high;
+        self
+    }
+}
+
+impl Texture for WorleyTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let d = self.worley.distance(p * self.scale);
+        mix(self.low, self.high, d.min(1.0))
+    }
+}